@@ -0,0 +1,18 @@
+# OSC 133 prompt marks and OSC 7 working directory reports for bash.
+# Source this from ~/.bashrc.
+
+if [[ -n "${__egui_term_loaded:-}" ]]; then
+    return
+fi
+__egui_term_loaded=1
+
+# A finish mark after an empty command line has no command to close and is
+# ignored by the terminal, so it is always sent.
+__egui_term_precmd() {
+    printf '\e]133;D;%s\a' "$?"
+    printf '\e]7;file://%s%s\a' "$HOSTNAME" "$PWD"
+}
+
+PROMPT_COMMAND="__egui_term_precmd${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+PS1="\[\e]133;A\a\]${PS1}\[\e]133;B\a\]"
+PS0="\e]133;C\a${PS0:-}"
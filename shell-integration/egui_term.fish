@@ -0,0 +1,21 @@
+# OSC 133 prompt marks and OSC 7 working directory reports for fish.
+# Source this from ~/.config/fish/config.fish.
+
+function __egui_term_prompt --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' $hostname $PWD
+    printf '\e]133;A\a'
+end
+
+function __egui_term_preexec --on-event fish_preexec
+    printf '\e]133;C\a'
+end
+
+function __egui_term_postexec --on-event fish_postexec
+    printf '\e]133;D;%s\a' $status
+end
+
+functions --copy fish_prompt __egui_term_original_prompt
+function fish_prompt
+    __egui_term_original_prompt
+    printf '\e]133;B\a'
+end
@@ -0,0 +1,229 @@
+//! Expect-style scripting of a running terminal, for integration tests
+//! of TUI apps.
+
+use alacritty_terminal::index::Point;
+use egui::{Key, Modifiers};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use super::export::{row_text, ExportRange};
+use super::{BackendCommand, TerminalBackend};
+use crate::bindings::{BindingAction, BindingsLayout, InputKind};
+
+/// How often the `wait_for_*` methods look at the terminal.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl TerminalBackend {
+    /// Types `text` as is.
+    pub fn send_text(&mut self, text: &str) {
+        self.process_command(BackendCommand::Write(text.as_bytes().to_vec()));
+    }
+
+    /// Presses `keys` one after another, encoded with the default
+    /// [`BindingsLayout`]. See [`Self::send_keys_with`].
+    pub fn send_keys(&mut self, keys: &[&str]) -> Result<()> {
+        self.send_keys_with(&BindingsLayout::new(), keys)
+    }
+
+    /// Presses `keys` one after another, encoded with `layout` in the
+    /// current terminal mode.
+    ///
+    /// A key is an egui key name, such as `"Enter"`, `"Up"` or `"F5"`,
+    /// optionally prefixed by `ctrl+`, `shift+`, `alt+` or `cmd+`. Keys
+    /// without a binding type their character; `alt+` prefixes it with
+    /// ESC.
+    pub fn send_keys_with(
+        &mut self,
+        layout: &BindingsLayout,
+        keys: &[&str],
+    ) -> Result<()> {
+        let mode = *self.term.lock().mode();
+        let mut bytes = Vec::new();
+        for spec in keys {
+            let (key, text, modifiers) = parse_key(spec)?;
+            match layout.get_action(InputKind::KeyCode(key), modifiers, mode) {
+                BindingAction::Char(c) => {
+                    bytes.extend_from_slice(c.to_string().as_bytes())
+                },
+                BindingAction::Esc(sequence) => {
+                    bytes.extend_from_slice(sequence.as_bytes())
+                },
+                _ => {
+                    let text = text
+                        .filter(|_| !modifiers.command && !modifiers.ctrl)
+                        .ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("key {spec:?} has no binding"),
+                            )
+                        })?;
+                    if modifiers.alt {
+                        bytes.push(0x1b);
+                    }
+                    if modifiers.shift {
+                        bytes.extend_from_slice(text.to_uppercase().as_bytes());
+                    } else {
+                        bytes.extend_from_slice(text.as_bytes());
+                    }
+                },
+            }
+        }
+        self.process_command(BackendCommand::Write(bytes));
+        Ok(())
+    }
+
+    /// Text of the viewport, one line per row without trailing blanks.
+    pub fn screen_text(&self) -> String {
+        self.rows(ExportRange::Screen)
+            .iter()
+            .map(|row| row_text(row))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Cursor position, line 0 being the top of the screen.
+    pub fn cursor_position(&self) -> Point {
+        self.term.lock().grid().cursor.point
+    }
+
+    /// Waits until `pattern` matches [`Self::screen_text`] and returns the
+    /// match. Fails with [`ErrorKind::TimedOut`] after `timeout`.
+    pub fn wait_for_regex(
+        &self,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<String> {
+        let regex = regex::Regex::new(pattern)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let screen = self.screen_text();
+            if let Some(found) = regex.find(&screen) {
+                return Ok(found.as_str().to_string());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("{pattern:?} did not appear on screen"),
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Waits until no output arrived for `quiet`. Returns `false` if the
+    /// terminal was still busy after `timeout`.
+    pub fn wait_for_idle(&self, quiet: Duration, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut seen = self.output_bytes.load(Ordering::Relaxed);
+        let mut since = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now.duration_since(since) >= quiet {
+                return true;
+            }
+            if now >= deadline {
+                return false;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+
+            let current = self.output_bytes.load(Ordering::Relaxed);
+            if current != seen {
+                seen = current;
+                since = Instant::now();
+            }
+        }
+    }
+}
+
+/// Splits `ctrl+shift+Up` into the key, the text it types and modifiers.
+fn parse_key(spec: &str) -> Result<(Key, Option<String>, Modifiers)> {
+    let invalid =
+        || Error::new(ErrorKind::InvalidInput, format!("unknown key {spec:?}"));
+
+    // `+` itself is a valid key name, so split off the last part first.
+    let (prefix, name) = match spec.strip_suffix("++") {
+        Some(prefix) => (prefix, "+"),
+        None => spec.rsplit_once('+').unwrap_or(("", spec)),
+    };
+    let mut modifiers = Modifiers::NONE;
+    for modifier in prefix.split('+').filter(|part| !part.is_empty()) {
+        match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => {
+                modifiers.ctrl = true;
+                // egui reports Ctrl as the command key outside of macOS.
+                modifiers.command |= !cfg!(target_os = "macos");
+            },
+            "shift" => modifiers.shift = true,
+            "alt" | "option" => modifiers.alt = true,
+            "cmd" | "command" | "super" => {
+                modifiers.mac_cmd = cfg!(target_os = "macos");
+                modifiers.command = true;
+            },
+            _ => return Err(invalid()),
+        }
+    }
+
+    let key = Key::from_name(name).ok_or_else(invalid)?;
+    let text = match key {
+        Key::Space => Some(" ".to_string()),
+        _ if name.chars().count() == 1 => Some(name.to_string()),
+        _ => None,
+    };
+    Ok((key, text, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_key;
+    use crate::HeadlessTerminal;
+    use egui::Key;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_key_specs() {
+        let (key, text, modifiers) = parse_key("ctrl+c").unwrap();
+        assert_eq!((key, text), (Key::C, Some("c".to_string())));
+        assert!(modifiers.ctrl && !modifiers.shift);
+
+        let (key, _, modifiers) = parse_key("Shift+Alt+Up").unwrap();
+        assert_eq!(key, Key::ArrowUp);
+        assert!(modifiers.shift && modifiers.alt);
+
+        assert_eq!(parse_key("ctrl++").unwrap().0, Key::Plus);
+        assert!(parse_key("hyper+x").is_err());
+        assert!(parse_key("NoSuchKey").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn drives_an_interactive_program() {
+        let mut terminal = HeadlessTerminal::run(
+            "/bin/sh",
+            &["-c", "printf 'name? '; read name; echo \"hi $name\"; cat"],
+            30,
+            6,
+        )
+        .unwrap();
+        let backend = terminal.backend_mut();
+        let timeout = Duration::from_secs(5);
+
+        backend.wait_for_regex(r"name\?", timeout).unwrap();
+        assert_eq!(backend.cursor_position().column.0, 6);
+        backend.send_text("bob");
+        backend.send_keys(&["Enter"]).unwrap();
+        assert_eq!(
+            backend.wait_for_regex(r"hi \w+", timeout).unwrap(),
+            "hi bob"
+        );
+        assert!(backend.wait_for_idle(Duration::from_millis(50), timeout));
+
+        backend.send_keys(&["ctrl+c"]).unwrap();
+        assert!(terminal.wait_for_exit(timeout));
+        assert!(terminal
+            .backend()
+            .screen_text()
+            .starts_with("name? bob\nhi bob"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Events a [`TerminalBackend`](super::TerminalBackend) sends to its host.
+
+use alacritty_terminal::event::{Event, EventListener, WindowSize};
+use alacritty_terminal::term::ClipboardType;
+use alacritty_terminal::vte::ansi::Rgb;
+use std::fmt;
+use std::sync::{mpsc, Arc};
+
+use super::process::ExitStatus;
+
+/// Alacritty's [`Event`], except that [`Self::ChildExit`] carries the full
+/// [`ExitStatus`] instead of only an exit code.
+#[derive(Clone)]
+pub enum PtyEvent {
+    MouseCursorDirty,
+    Title(String),
+    ResetTitle,
+    ClipboardStore(ClipboardType, String),
+    ClipboardLoad(
+        ClipboardType,
+        Arc<dyn Fn(&str) -> String + Sync + Send + 'static>,
+    ),
+    ColorRequest(usize, Arc<dyn Fn(Rgb) -> String + Sync + Send + 'static>),
+    PtyWrite(String),
+    TextAreaSizeRequest(
+        Arc<dyn Fn(WindowSize) -> String + Sync + Send + 'static>,
+    ),
+    CursorBlinkingChange,
+    Wakeup,
+    Bell,
+    /// The session ended, sent after [`Self::ChildExit`].
+    Exit,
+    /// The process behind the terminal ended.
+    ChildExit(ExitStatus),
+}
+
+impl From<Event> for PtyEvent {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::MouseCursorDirty => Self::MouseCursorDirty,
+            Event::Title(title) => Self::Title(title),
+            Event::ResetTitle => Self::ResetTitle,
+            Event::ClipboardStore(ty, text) => Self::ClipboardStore(ty, text),
+            Event::ClipboardLoad(ty, format) => Self::ClipboardLoad(ty, format),
+            Event::ColorRequest(index, format) => {
+                Self::ColorRequest(index, format)
+            },
+            Event::PtyWrite(text) => Self::PtyWrite(text),
+            Event::TextAreaSizeRequest(format) => {
+                Self::TextAreaSizeRequest(format)
+            },
+            Event::CursorBlinkingChange => Self::CursorBlinkingChange,
+            Event::Wakeup => Self::Wakeup,
+            Event::Bell => Self::Bell,
+            Event::Exit => Self::Exit,
+            Event::ChildExit(code) => Self::ChildExit(ExitStatus {
+                code: Some(code),
+                signal: None,
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for PtyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClipboardStore(ty, text) => {
+                write!(f, "ClipboardStore({ty:?}, {text})")
+            },
+            Self::ClipboardLoad(ty, _) => write!(f, "ClipboardLoad({ty:?})"),
+            Self::TextAreaSizeRequest(_) => write!(f, "TextAreaSizeRequest"),
+            Self::ColorRequest(index, _) => write!(f, "ColorRequest({index})"),
+            Self::PtyWrite(text) => write!(f, "PtyWrite({text})"),
+            Self::Title(title) => write!(f, "Title({title})"),
+            Self::CursorBlinkingChange => write!(f, "CursorBlinkingChange"),
+            Self::MouseCursorDirty => write!(f, "MouseCursorDirty"),
+            Self::ResetTitle => write!(f, "ResetTitle"),
+            Self::Wakeup => write!(f, "Wakeup"),
+            Self::Bell => write!(f, "Bell"),
+            Self::Exit => write!(f, "Exit"),
+            Self::ChildExit(status) => write!(f, "ChildExit({status:?})"),
+        }
+    }
+}
+
+/// Listener handed to the terminal and to the source threads.
+#[derive(Clone)]
+pub struct EventProxy(pub(super) mpsc::Sender<PtyEvent>);
+
+impl EventProxy {
+    pub(crate) fn send(&self, event: PtyEvent) {
+        let _ = self.0.send(event);
+    }
+}
+
+impl EventListener for EventProxy {
+    fn send_event(&self, event: Event) {
+        self.send(event.into());
+    }
+}
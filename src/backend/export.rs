@@ -0,0 +1,356 @@
+use alacritty_terminal::term::cell::{Cell, Flags};
+use alacritty_terminal::vte::ansi::{Color, NamedColor};
+use egui::Color32;
+use std::fmt::Write;
+
+use crate::theme::TerminalTheme;
+
+/// Part of the terminal written by [`crate::TerminalBackend::export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportRange {
+    /// The lines currently shown in the viewport.
+    Screen,
+    /// The whole history, including the overflow store, and the screen.
+    Scrollback,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExportFormat {
+    PlainText,
+    /// Text with ANSI SGR sequences that restore colors and attributes.
+    Ansi,
+    /// Standalone HTML document colored with the given theme.
+    Html(Box<TerminalTheme>),
+}
+
+const SGR_FLAGS: [(Flags, &str); 11] = [
+    (Flags::BOLD, "1"),
+    (Flags::DIM, "2"),
+    (Flags::ITALIC, "3"),
+    (Flags::UNDERLINE, "4"),
+    (Flags::DOUBLE_UNDERLINE, "4:2"),
+    (Flags::UNDERCURL, "4:3"),
+    (Flags::DOTTED_UNDERLINE, "4:4"),
+    (Flags::DASHED_UNDERLINE, "4:5"),
+    (Flags::INVERSE, "7"),
+    (Flags::HIDDEN, "8"),
+    (Flags::STRIKEOUT, "9"),
+];
+
+pub(crate) fn export_rows(rows: &[Vec<Cell>], format: &ExportFormat) -> String {
+    match format {
+        ExportFormat::PlainText => plain_text(rows),
+        ExportFormat::Ansi => ansi(rows),
+        ExportFormat::Html(theme) => html(rows, theme),
+    }
+}
+
+/// Cells of a row that carry text, trailing blanks removed unless the row
+/// wraps into the next one.
+fn visible_cells(row: &[Cell]) -> (&[Cell], bool) {
+    let wraps = row
+        .last()
+        .is_some_and(|c| c.flags.contains(Flags::WRAPLINE));
+    if wraps {
+        return (row, true);
+    }
+
+    let used = row
+        .iter()
+        .rposition(|cell| {
+            cell.c != ' '
+                || cell.bg != Color::Named(NamedColor::Background)
+                || cell
+                    .flags
+                    .intersects(Flags::INVERSE | Flags::ALL_UNDERLINES)
+        })
+        .map_or(0, |index| index + 1);
+    (&row[..used], false)
+}
+
+fn push_cell_text(out: &mut String, cell: &Cell) {
+    out.push(cell.c);
+    if let Some(zerowidth) = cell.zerowidth() {
+        out.extend(zerowidth);
+    }
+}
+
+fn is_spacer(cell: &Cell) -> bool {
+    cell.flags
+        .intersects(Flags::WIDE_CHAR_SPACER | Flags::LEADING_WIDE_CHAR_SPACER)
+}
+
+/// Text of a single row without trailing blanks.
+pub(crate) fn row_text(row: &[Cell]) -> String {
+    let mut text = String::new();
+    for cell in row.iter().filter(|cell| !is_spacer(cell)) {
+        push_cell_text(&mut text, cell);
+    }
+    text.truncate(text.trim_end_matches(' ').len());
+    text
+}
+
+fn plain_text(rows: &[Vec<Cell>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let (cells, wraps) = visible_cells(row);
+        for cell in cells.iter().filter(|cell| !is_spacer(cell)) {
+            push_cell_text(&mut out, cell);
+        }
+        if !wraps {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn ansi(rows: &[Vec<Cell>]) -> String {
+    let default = Cell::default();
+    let mut out = String::new();
+    let mut current = (default.fg, default.bg, Flags::empty());
+
+    for row in rows {
+        let (cells, wraps) = visible_cells(row);
+        for cell in cells.iter().filter(|cell| !is_spacer(cell)) {
+            let style = (cell.fg, cell.bg, style_flags(cell.flags));
+            if style != current {
+                out.push_str(&sgr(style.0, style.1, style.2));
+                current = style;
+            }
+            push_cell_text(&mut out, cell);
+        }
+        if !wraps {
+            if current != (default.fg, default.bg, Flags::empty()) {
+                out.push_str("\x1b[0m");
+                current = (default.fg, default.bg, Flags::empty());
+            }
+            out.push('\n');
+        }
+    }
+    if current != (default.fg, default.bg, Flags::empty()) {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+fn style_flags(flags: Flags) -> Flags {
+    let mut style = Flags::empty();
+    for (flag, _) in SGR_FLAGS {
+        if flags.contains(flag) {
+            style |= flag;
+        }
+    }
+    style
+}
+
+fn sgr(fg: Color, bg: Color, flags: Flags) -> String {
+    let mut params = vec!["0".to_string()];
+    for (flag, code) in SGR_FLAGS {
+        if flags.contains(flag) {
+            params.push(code.to_string());
+        }
+    }
+    params.extend(sgr_color(fg, false));
+    params.extend(sgr_color(bg, true));
+    format!("\x1b[{}m", params.join(";"))
+}
+
+fn sgr_color(color: Color, background: bool) -> Option<String> {
+    let base = if background { 40 } else { 30 };
+    match color {
+        Color::Named(NamedColor::Foreground | NamedColor::Background) => None,
+        Color::Named(named) => {
+            let index = named_index(named)?;
+            if index < 8 {
+                Some((base + index).to_string())
+            } else {
+                Some((base + 60 + index - 8).to_string())
+            }
+        },
+        Color::Indexed(index) => Some(format!("{};5;{index}", base + 8)),
+        Color::Spec(rgb) => {
+            Some(format!("{};2;{};{};{}", base + 8, rgb.r, rgb.g, rgb.b))
+        },
+    }
+}
+
+/// Palette index of a named color. Dim variants map to their normal color,
+/// the dim attribute is exported separately.
+fn named_index(named: NamedColor) -> Option<u8> {
+    let index = named as usize;
+    match named {
+        _ if index < 16 => Some(index as u8),
+        NamedColor::DimBlack
+        | NamedColor::DimRed
+        | NamedColor::DimGreen
+        | NamedColor::DimYellow
+        | NamedColor::DimBlue
+        | NamedColor::DimMagenta
+        | NamedColor::DimCyan
+        | NamedColor::DimWhite => {
+            Some((index - NamedColor::DimBlack as usize) as u8)
+        },
+        _ => None,
+    }
+}
+
+fn css_color(color: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+fn html(rows: &[Vec<Cell>], theme: &TerminalTheme) -> String {
+    let foreground = theme.get_color(Color::Named(NamedColor::Foreground));
+    let background = theme.get_color(Color::Named(NamedColor::Background));
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Terminal</title>\n<style>\n\
+         body {{ margin: 0; background: {bg}; }}\n\
+         pre {{ margin: 0; padding: 8px; color: {fg}; background: {bg}; \
+         font-family: monospace; }}\n\
+         </style>\n</head>\n<body>\n<pre>",
+        fg = css_color(foreground),
+        bg = css_color(background),
+    );
+
+    for row in rows {
+        let (cells, wraps) = visible_cells(row);
+        let mut span: Option<String> = None;
+        for cell in cells.iter().filter(|cell| !is_spacer(cell)) {
+            let style = css_style(cell, theme, foreground, background);
+            if span != style {
+                if span.is_some() {
+                    out.push_str("</span>");
+                }
+                if let Some(style) = &style {
+                    let _ = write!(out, "<span style=\"{style}\">");
+                }
+                span = style;
+            }
+
+            let mut text = String::new();
+            push_cell_text(&mut text, cell);
+            for c in text.chars() {
+                match c {
+                    '&' => out.push_str("&amp;"),
+                    '<' => out.push_str("&lt;"),
+                    '>' => out.push_str("&gt;"),
+                    '"' => out.push_str("&quot;"),
+                    c => out.push(c),
+                }
+            }
+        }
+        if span.is_some() {
+            out.push_str("</span>");
+        }
+        if !wraps {
+            out.push('\n');
+        }
+    }
+
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+fn css_style(
+    cell: &Cell,
+    theme: &TerminalTheme,
+    foreground: Color32,
+    background: Color32,
+) -> Option<String> {
+    let mut fg = theme.get_color(cell.fg);
+    let mut bg = theme.get_color(cell.bg);
+    if cell.flags.contains(Flags::INVERSE) {
+        std::mem::swap(&mut fg, &mut bg);
+    }
+    if cell.flags.contains(Flags::HIDDEN) {
+        fg = bg;
+    }
+
+    let mut style = Vec::new();
+    if fg != foreground {
+        style.push(format!("color: {}", css_color(fg)));
+    }
+    if bg != background {
+        style.push(format!("background: {}", css_color(bg)));
+    }
+    if cell.flags.contains(Flags::BOLD) {
+        style.push("font-weight: bold".to_string());
+    }
+    if cell.flags.contains(Flags::ITALIC) {
+        style.push("font-style: italic".to_string());
+    }
+    if cell.flags.contains(Flags::DIM) {
+        style.push("opacity: 0.7".to_string());
+    }
+
+    let mut decorations = Vec::new();
+    if cell.flags.intersects(Flags::ALL_UNDERLINES) {
+        decorations.push("underline");
+    }
+    if cell.flags.contains(Flags::STRIKEOUT) {
+        decorations.push("line-through");
+    }
+    if !decorations.is_empty() {
+        style.push(format!("text-decoration: {}", decorations.join(" ")));
+    }
+
+    (!style.is_empty()).then(|| style.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_rows, ExportFormat};
+    use alacritty_terminal::term::cell::{Cell, Flags};
+    use alacritty_terminal::vte::ansi::{Color, NamedColor, Rgb};
+
+    fn row(text: &str, columns: usize) -> Vec<Cell> {
+        let mut cells: Vec<Cell> = text
+            .chars()
+            .map(|c| Cell {
+                c,
+                ..Cell::default()
+            })
+            .collect();
+        cells.resize(columns, Cell::default());
+        cells
+    }
+
+    fn styled_rows() -> Vec<Vec<Cell>> {
+        let mut first = row("ok <b>", 10);
+        first[0].fg = Color::Named(NamedColor::Red);
+        first[0].flags = Flags::BOLD;
+        first[1].fg = Color::Spec(Rgb { r: 1, g: 2, b: 3 });
+        vec![first, row("plain", 10)]
+    }
+
+    #[test]
+    fn plain_text_trims_and_joins_wrapped_rows() {
+        let mut wrapped = row("abc", 3);
+        wrapped[2].flags = Flags::WRAPLINE;
+        let rows = vec![wrapped, row("def", 6), row("", 6)];
+        assert_eq!(export_rows(&rows, &ExportFormat::PlainText), "abcdef\n\n");
+    }
+
+    #[test]
+    fn ansi_restores_colors_and_attributes() {
+        assert_eq!(
+            export_rows(&styled_rows(), &ExportFormat::Ansi),
+            "\x1b[0;1;31mo\x1b[0;38;2;1;2;3mk\x1b[0m <b>\nplain\n"
+        );
+    }
+
+    #[test]
+    fn html_is_escaped_and_styled_by_theme() {
+        let html =
+            export_rows(&styled_rows(), &ExportFormat::Html(Box::default()));
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(
+            "<span style=\"color: #ac4242; font-weight: bold\">o</span>"
+        ));
+        assert!(html.contains("<span style=\"color: #010203\">k</span>"));
+        assert!(html.contains(" &lt;b&gt;\nplain\n</pre>"));
+    }
+}
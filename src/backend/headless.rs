@@ -0,0 +1,383 @@
+//! A terminal without a UI, for CLI tools, background workers and tests.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use super::process::ExitStatus;
+use super::settings::BackendSettings;
+use super::source::{BackendError, BackendSource};
+use super::{BackendCommand, PtyEvent, TerminalBackend, Waker};
+use crate::types::Size;
+
+/// A [`TerminalBackend`] that owns its event channel.
+///
+/// Output is parsed on background threads as it arrives; [`Self::pump`]
+/// only collects the events the backend sent, such as the exit of the
+/// child.
+pub struct HeadlessTerminal {
+    backend: TerminalBackend,
+    events: Receiver<(u64, PtyEvent)>,
+    exited: bool,
+    exit_status: Option<ExitStatus>,
+}
+
+impl HeadlessTerminal {
+    /// Runs `program` with `args` on a PTY of `columns` x `lines` cells.
+    pub fn run(
+        program: &str,
+        args: &[&str],
+        columns: u16,
+        lines: u16,
+    ) -> Result<Self, BackendError> {
+        let settings = BackendSettings {
+            shell: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..BackendSettings::default()
+        };
+        Self::new(settings, columns, lines)
+    }
+
+    /// Spawns the shell of `settings` on a PTY of `columns` x `lines`
+    /// cells. The initial size hints of `settings` are replaced.
+    pub fn new(
+        settings: BackendSettings,
+        columns: u16,
+        lines: u16,
+    ) -> Result<Self, BackendError> {
+        let (sender, events) = mpsc::channel();
+        let settings = sized(settings, columns, lines);
+        let backend =
+            TerminalBackend::new(0, Waker::default(), sender, settings)?;
+        Ok(Self::from_backend(backend, events))
+    }
+
+    /// Drives the terminal from `source` at `columns` x `lines` cells.
+    pub fn with_source(
+        source: Box<dyn BackendSource>,
+        settings: BackendSettings,
+        columns: u16,
+        lines: u16,
+    ) -> Result<Self, BackendError> {
+        let (sender, events) = mpsc::channel();
+        let settings = sized(settings, columns, lines);
+        let backend = TerminalBackend::with_source(
+            0,
+            Waker::default(),
+            sender,
+            source,
+            settings,
+        )?;
+        Ok(Self::from_backend(backend, events))
+    }
+
+    fn from_backend(
+        backend: TerminalBackend,
+        events: Receiver<(u64, PtyEvent)>,
+    ) -> Self {
+        Self {
+            backend,
+            events,
+            exited: false,
+            exit_status: None,
+        }
+    }
+
+    pub fn backend(&self) -> &TerminalBackend {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut TerminalBackend {
+        &mut self.backend
+    }
+
+    /// Waits up to `timeout` for the next event, then collects everything
+    /// else already queued.
+    pub fn pump(&mut self, timeout: Duration) -> Vec<PtyEvent> {
+        let mut events = Vec::new();
+        match self.events.recv_timeout(timeout) {
+            Ok((_, event)) => events.push(event),
+            Err(RecvTimeoutError::Timeout) => return events,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.exited = true;
+                return events;
+            },
+        }
+        events.extend(self.events.try_iter().map(|(_, event)| event));
+
+        for event in &events {
+            match event {
+                PtyEvent::ChildExit(status) => self.exit_status = Some(*status),
+                PtyEvent::Exit => self.exited = true,
+                _ => {},
+            }
+        }
+        events
+    }
+
+    /// Pumps events until the child exited or `timeout` passed. Returns
+    /// whether the child exited.
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.exited {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            self.pump(deadline - now);
+        }
+        self.exited
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.exited
+    }
+
+    /// Exit code of the child, once it exited normally.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_status?.code
+    }
+
+    /// How the child ended, once it exited.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    pub fn write(&mut self, bytes: impl Into<Vec<u8>>) {
+        self.backend
+            .process_command(BackendCommand::Write(bytes.into()));
+    }
+
+    pub fn resize(&mut self, columns: u16, lines: u16) {
+        self.backend.process_command(BackendCommand::Resize(
+            Size::new(columns as f32, lines as f32),
+            Size::new(1.0, 1.0),
+        ));
+    }
+
+    /// Rows of the viewport without trailing blanks.
+    pub fn screen_lines(&self) -> Vec<String> {
+        self.backend
+            .screen_text()
+            .split('\n')
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Headless cells are one unit large, so the layout size is the grid size.
+fn sized(
+    settings: BackendSettings,
+    columns: u16,
+    lines: u16,
+) -> BackendSettings {
+    BackendSettings {
+        initial_layout_size: Some(Size::new(columns as f32, lines as f32)),
+        initial_cell_metrics: Some(Size::new(1.0, 1.0)),
+        ..settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeadlessTerminal;
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_command_and_reads_screen() {
+        let mut terminal = HeadlessTerminal::run(
+            "/bin/sh",
+            &["-c", "printf 'one\\n  two  \\n'; read line; exit 4"],
+            20,
+            5,
+        )
+        .unwrap();
+        terminal.write("bye\r");
+
+        assert!(terminal.wait_for_exit(Duration::from_secs(5)));
+        assert_eq!(terminal.exit_code(), Some(4));
+        assert_eq!(terminal.screen_lines(), ["one", "  two", "bye", "", ""]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shuts_down_with_the_signal_policy() {
+        use crate::{BackendSettings, ShutdownPolicy, Signal};
+        use std::time::Instant;
+
+        let run = |script: &str| {
+            let settings = BackendSettings {
+                shell: "/bin/sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+                shutdown: ShutdownPolicy {
+                    signals: vec![Signal::Hangup, Signal::Kill],
+                    grace: Duration::from_millis(300),
+                },
+                ..BackendSettings::default()
+            };
+            let mut terminal = HeadlessTerminal::new(settings, 20, 5).unwrap();
+            let timeout = Duration::from_secs(5);
+            terminal.backend().wait_for_regex("ready", timeout).unwrap();
+            let started = Instant::now();
+            terminal.backend_mut().shutdown().unwrap().join().unwrap();
+            assert!(terminal.wait_for_exit(timeout));
+            (terminal.exit_status().unwrap().signal, started.elapsed())
+        };
+
+        let (signal, elapsed) = run("echo ready; sleep 30");
+        assert_eq!(signal, Some(Signal::Hangup));
+        assert!(elapsed < Duration::from_millis(300));
+
+        let (signal, elapsed) = run("trap '' HUP; echo ready; sleep 30");
+        assert_eq!(signal, Some(Signal::Kill));
+        assert!(elapsed >= Duration::from_millis(300));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn shutdown_stops_the_reader_of_an_open_device() {
+        use crate::{BackendSettings, DeviceSource, ShutdownPolicy};
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::time::Instant;
+
+        // The test keeps both sides of the pty, so the device never closes.
+        let (mut master, mut slave) = (0, 0);
+        let res = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(res, 0);
+        let (_master, _slave) = unsafe {
+            (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+        };
+        let path =
+            std::fs::read_link(format!("/proc/self/fd/{slave}")).unwrap();
+
+        let settings = BackendSettings {
+            shutdown: ShutdownPolicy {
+                signals: Vec::new(),
+                grace: Duration::from_secs(5),
+            },
+            ..BackendSettings::default()
+        };
+        let mut terminal = HeadlessTerminal::with_source(
+            Box::new(DeviceSource::open(path).unwrap()),
+            settings,
+            20,
+            5,
+        )
+        .unwrap();
+        let started = Instant::now();
+        terminal.backend_mut().shutdown().unwrap().join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_stops_the_reader_of_a_pipe_held_by_a_grandchild() {
+        use crate::{
+            BackendSettings, BackendSource, PipeSource, ShutdownPolicy,
+        };
+        use std::time::Instant;
+
+        // The background job keeps the output pipe open after the child.
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "sleep 30 & exec sleep 30"]);
+        let source = PipeSource::spawn(command).unwrap();
+        let pid = source.process_id().unwrap() as libc::pid_t;
+        let settings = BackendSettings {
+            shutdown: ShutdownPolicy {
+                signals: Vec::new(),
+                grace: Duration::from_secs(5),
+            },
+            ..BackendSettings::default()
+        };
+        let mut terminal =
+            HeadlessTerminal::with_source(Box::new(source), settings, 20, 5)
+                .unwrap();
+        let started = Instant::now();
+        terminal.backend_mut().shutdown().unwrap().join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // Without signals in the policy the child is left alone.
+        assert_eq!(unsafe { libc::kill(pid, 0) }, 0);
+        unsafe { libc::kill(-pid, libc::SIGKILL) };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn holds_the_terminal_and_restarts_on_enter() {
+        use crate::{BackendSettings, ExitBehavior};
+
+        let settings = BackendSettings {
+            shell: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), "echo started; exit 3".to_string()],
+            exit_behavior: ExitBehavior::Hold,
+            ..BackendSettings::default()
+        };
+        let mut terminal = HeadlessTerminal::new(settings, 60, 8).unwrap();
+        let timeout = Duration::from_secs(5);
+        terminal
+            .backend()
+            .wait_for_regex("restart", timeout)
+            .unwrap();
+        assert!(!terminal.wait_for_exit(Duration::from_millis(200)));
+        assert_eq!(terminal.exit_code(), Some(3));
+        assert!(terminal.backend().has_exited());
+        assert_eq!(
+            terminal.screen_lines()[..2],
+            [
+                "started",
+                "[process exited with status 3 — press Enter to restart]"
+            ]
+        );
+
+        terminal.write("x");
+        assert_eq!(terminal.screen_lines()[2], "");
+        terminal.write("\r");
+        terminal
+            .backend()
+            .wait_for_regex("(?s)started.*restart.*started.*restart", timeout)
+            .unwrap();
+        terminal.backend_mut().shutdown().unwrap().join().unwrap();
+        assert!(terminal.wait_for_exit(timeout));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_startup_input_once_the_shell_is_ready() {
+        use crate::{BackendSettings, StartupInput, StartupReady};
+
+        // The echo of the input shows where the cursor was when it came.
+        let run = |prompt: &str, startup_ready, timeout| {
+            let script = format!(
+                "echo init; sleep 0.5; printf '{prompt}> '; \
+                 read a; read b; echo got $a $b"
+            );
+            let settings = BackendSettings {
+                shell: "/bin/sh".to_string(),
+                args: vec!["-c".to_string(), script],
+                startup_input: vec![
+                    StartupInput::Command("one".to_string()),
+                    StartupInput::Raw(b"two\r".to_vec()),
+                ],
+                startup_ready,
+                startup_timeout: Duration::from_millis(timeout),
+                ..BackendSettings::default()
+            };
+            let mut terminal = HeadlessTerminal::new(settings, 40, 5).unwrap();
+            assert!(terminal.wait_for_exit(Duration::from_secs(5)));
+            terminal.screen_lines()
+        };
+
+        let early = ["init", "one", "two", "> got one two", ""];
+        let prompted = run("\\033]133;A\\007", StartupReady::Prompt, 3000);
+        assert_eq!(prompted, ["init", "> one", "two", "got one two", ""]);
+        assert_eq!(run("", StartupReady::Prompt, 200), early);
+        assert_eq!(run("", StartupReady::Output, 3000), early);
+    }
+}
@@ -1,46 +1,109 @@
+mod automation;
+mod event;
+pub mod export;
+pub mod headless;
+mod overflow;
+pub mod playback;
+pub mod process;
+pub mod recorder;
 pub mod settings;
+pub mod shell_integration;
+pub mod source;
+mod waker;
 
+use crate::paste::{paste_payload, PasteRequest};
 use crate::types::Size;
-use alacritty_terminal::event::{
-    Event, EventListener, Notify, OnResize, WindowSize,
-};
-use alacritty_terminal::event_loop::{EventLoop, Notifier};
+use alacritty_terminal::event::WindowSize;
 use alacritty_terminal::grid::{Dimensions, Scroll};
-use alacritty_terminal::index::{Column, Direction, Line, Point, Side};
+use alacritty_terminal::index::{
+    Boundary, Column, Direction, Line, Point, Side,
+};
 use alacritty_terminal::selection::{
     Selection, SelectionRange, SelectionType as AlacrittySelectionType,
 };
 use alacritty_terminal::sync::FairMutex;
 use alacritty_terminal::term::search::{Match, RegexIter, RegexSearch};
 use alacritty_terminal::term::{
-    self, cell::Cell, test::TermSize, viewport_to_point, Term, TermMode,
+    self,
+    cell::{Cell, LineLength},
+    test::TermSize,
+    viewport_to_point, Term, TermMode,
 };
+#[cfg(windows)]
 use alacritty_terminal::tty;
+use alacritty_terminal::vte::ansi::Processor;
 use egui::Modifiers;
-use settings::BackendSettings;
+use event::EventProxy;
+pub use event::PtyEvent;
+use export::{export_rows, ExportFormat, ExportRange};
+use overflow::{OverflowMatches, OverflowStore};
+use playback::{PlaybackCommand, PlaybackStatus, Player, Recording};
+use process::ExitStatus;
+use recorder::{record, AsciicastRecorder, RecordingSettings, SharedRecorder};
+use settings::{BackendSettings, ExitBehavior, ShutdownPolicy, TerminalConfig};
+use shell_integration::{
+    CommandInfo, CommandTracker, MarkPoint, SharedCommandTracker,
+    TRACKING_MARGIN,
+};
+use source::{
+    BackendError, BackendSource, Notifier, PtySource, SpawnError, Startup,
+};
 use std::borrow::Cow;
 use std::cmp::min;
 use std::collections::HashSet;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
 use std::ops::{Index, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+pub use waker::Waker;
+
+/// How often the event subscription checks whether the backend shut down
+/// after the session had already ended.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Leaves the alternate screen and the modes a program may have left on
+/// when it died, before the exit banner of [`ExitBehavior::Hold`].
+const EXIT_RESET: &str = "\x1b[?1049l\x1b[?1l\x1b>\x1b[?25h\x1b[?7h\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1006l\x1b[?2004l\x1b7\x1b[r\x1b8\x1b[0m";
+
+pub(crate) const URL_REGEX: &str = r#"(ipfs:|ipns:|magnet:|mailto:|gemini://|gopher://|https://|http://|news:|file://|git://|ssh:|ftp://)[^\u{0000}-\u{001F}\u{007F}-\u{009F}<>"\s{-}\^⟨⟩`]+"#;
 
 pub type TerminalMode = TermMode;
-pub type PtyEvent = Event;
+pub type Osc52 = term::Osc52;
 pub type SelectionType = AlacrittySelectionType;
 
 #[derive(Debug, Clone)]
 pub enum BackendCommand {
     Write(Vec<u8>),
+    Paste(String),
     Scroll(i32),
     ScrollPageUp,
     ScrollPageDown,
     Resize(Size, Size),
     SelectStart(SelectionType, f32, f32),
     SelectUpdate(f32, f32),
+    ClearSelection,
     ProcessLink(LinkAction, Point),
     MouseReport(MouseButton, Modifiers, Point, bool),
+    UpdateConfig(TerminalConfig),
+    StartRecording(RecordingSettings),
+    StopRecording,
+    Playback(PlaybackCommand),
+    /// Scrolls the previous shell prompt to the top, see
+    /// [`shell_integration`].
+    PreviousPrompt,
+    NextPrompt,
+    SelectLastCommandOutput,
+    /// Selects the output of the command with this [`CommandInfo::id`].
+    SelectCommandOutput(u64),
+    /// Folds the output of the command with this [`CommandInfo::id`] into
+    /// a summary line, or unfolds it.
+    ToggleFold(u64),
+    /// Toggles the fold of the last finished command.
+    ToggleLastFold,
 }
 
 #[derive(Debug, Clone)]
@@ -91,6 +154,8 @@ pub struct SearchState {
     pub current_match_index: usize,
     pub active: bool,
     pub no_match: bool,
+    /// Matches in the overflow store, searched as lines are stored.
+    overflow: OverflowMatches,
 }
 
 impl SearchState {
@@ -121,7 +186,27 @@ impl SearchState {
     }
 
     pub fn update_matches(&mut self, term: &Term<EventProxy>) {
+        self.update_matches_with(term, None);
+    }
+
+    /// Like [`Self::update_matches`], with the matches in the overflow
+    /// `store` in front. They are at the lines above
+    /// [`Term::topmost_line`] that the overflow rows are shown at.
+    fn update_matches_with(
+        &mut self,
+        term: &Term<EventProxy>,
+        store: Option<&mut OverflowStore>,
+    ) {
         if let Some(ref mut regex) = self.regex {
+            let mut matches = match store {
+                Some(store) => self.overflow.update(
+                    &self.query,
+                    store,
+                    term.topmost_line(),
+                ),
+                None => Vec::new(),
+            };
+
             // Search the entire terminal buffer (scrollback + visible viewport).
             let start = term
                 .line_search_left(Point::new(term.topmost_line(), Column(0)));
@@ -130,9 +215,14 @@ impl SearchState {
                 Column(0),
             ));
 
-            self.matches =
-                RegexIter::new(start, end, Direction::Right, term, regex)
-                    .collect();
+            matches.extend(RegexIter::new(
+                start,
+                end,
+                Direction::Right,
+                term,
+                regex,
+            ));
+            self.matches = matches;
 
             // Build a HashSet of all points covered by matches for O(1) lookup
             // during rendering (avoids linear scan per cell).
@@ -280,139 +370,415 @@ impl From<TerminalSize> for WindowSize {
     }
 }
 
+struct PlaybackHandle {
+    commands: Sender<PlaybackCommand>,
+    status: Arc<Mutex<PlaybackStatus>>,
+}
+
 pub struct TerminalBackend {
     id: u64,
     pty_id: u32,
     url_regex: RegexSearch,
+    smart_selection_regexes: Vec<RegexSearch>,
     term: Arc<FairMutex<Term<EventProxy>>>,
     size: TerminalSize,
-    notifier: Notifier,
+    /// `None` for playback backends, which are read-only.
+    notifier: Option<Notifier>,
+    playback: Option<PlaybackHandle>,
     last_content: RenderableContent,
-    _event_loop_thread: Option<std::thread::JoinHandle<()>>,
-    _event_loop_thread_pty: Option<
-        std::thread::JoinHandle<(
-            alacritty_terminal::event_loop::EventLoop<
-                alacritty_terminal::tty::Pty,
-                EventProxy,
-            >,
-            alacritty_terminal::event_loop::State,
-        )>,
-    >,
+    pending_paste: Option<PasteRequest>,
+    overflow: Option<Arc<Mutex<OverflowStore>>>,
+    /// Number of overflow lines scrolled past the top of the grid history.
+    overflow_offset: usize,
+    recorder: SharedRecorder,
+    /// `None` for playback backends.
+    commands: Option<SharedCommandTracker>,
+    /// Total output parsed so far, to tell when the terminal went idle.
+    output_bytes: Arc<AtomicUsize>,
+    /// Environment written into recording headers.
+    recording_env: Vec<(&'static str, String)>,
+    /// Settings of a spawned shell, to start another one like it.
+    spawn_settings: Option<BackendSettings>,
+    /// The pty or tty device, see [`Self::foreground_process_group`].
+    terminal_device: Option<std::fs::File>,
+    shutdown_policy: ShutdownPolicy,
+    exit_behavior: ExitBehavior,
+    /// Set by [`Self::shutdown`], ends the event subscription.
+    closing: Arc<AtomicBool>,
+    /// Listener of the terminal, for sessions started by [`Self::respawn`].
+    event_proxy: EventProxy,
+    /// Source, playback and event threads, joined by [`Self::shutdown`].
+    threads: Vec<JoinHandle<()>>,
 }
 
 impl TerminalBackend {
     pub fn new(
         id: u64,
-        app_context: egui::Context,
+        waker: impl Into<Waker>,
         pty_event_proxy_sender: Sender<(u64, PtyEvent)>,
         settings: BackendSettings,
-    ) -> Result<Self> {
-        // Copy out the initial size hints before `settings` is moved into the
-        // PTY config below. `Size` is `Copy`, so a plain read suffices.
-        let initial_layout_size = settings.initial_layout_size;
-        let initial_cell_metrics = settings.initial_cell_metrics;
+    ) -> std::result::Result<Self, BackendError> {
+        let terminal_size = initial_terminal_size(&settings);
+        let source = spawn_shell(&settings, terminal_size, id)?;
+        let mut recording_env = vec![("SHELL", settings.shell.clone())];
+        if let Some(term) = settings.env.get("TERM") {
+            recording_env.push(("TERM", term.clone()));
+        }
 
-        let pty_config = tty::Options {
-            shell: Some(tty::Shell::new(settings.shell, settings.args)),
-            working_directory: settings.working_directory,
-            env: settings.env,
-            ..tty::Options::default()
+        let mut backend = Self::from_source(
+            id,
+            waker.into(),
+            pty_event_proxy_sender,
+            Box::new(source),
+            settings.clone(),
+            terminal_size,
+            recording_env,
+        )?;
+        backend.spawn_settings = Some(settings);
+        Ok(backend)
+    }
+
+    /// Spawns another shell like this one in its [`Self::current_dir`],
+    /// with the same settings and environment, for "new tab here" and
+    /// split actions. Only backends created by [`Self::new`] support it.
+    pub fn new_in_current_dir(
+        &self,
+        id: u64,
+        waker: impl Into<Waker>,
+        pty_event_proxy_sender: Sender<(u64, PtyEvent)>,
+    ) -> std::result::Result<Self, BackendError> {
+        let Some(settings) = &self.spawn_settings else {
+            return Err(BackendError::Io(Error::new(
+                ErrorKind::Unsupported,
+                "the backend did not spawn a shell",
+            )));
         };
-        let config = term::Config::default();
-        let terminal_size = match (initial_layout_size, initial_cell_metrics) {
-            (Some(layout), Some(cell)) if cell.width > 0.0 && cell.height > 0.0 => {
-                let lines = (layout.height / cell.height.floor()) as u16;
-                let cols = (layout.width / cell.width.floor()) as u16;
-                if lines > 0 && cols > 0 {
-                    TerminalSize {
-                        layout_size: layout,
-                        cell_width: cell.width as u16,
-                        cell_height: cell.height as u16,
-                        num_lines: lines,
-                        num_cols: cols,
-                    }
-                } else {
-                    TerminalSize::default()
-                }
-            }
-            _ => TerminalSize::default(),
+        let settings = BackendSettings {
+            working_directory: self
+                .current_dir()
+                .or_else(|| settings.working_directory.clone()),
+            initial_layout_size: Some(self.size.layout_size),
+            initial_cell_metrics: Some(Size::new(
+                self.size.cell_width as f32,
+                self.size.cell_height as f32,
+            )),
+            ..settings.clone()
         };
-        let pty = tty::new(&pty_config, terminal_size.into(), id)?;
-        #[cfg(not(windows))]
-        let pty_id = pty.child().id();
-        #[cfg(windows)]
-        let pty_id = pty
-            .child_watcher()
-            .pid()
-            .ok_or(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Failed to get child process ID",
-            ))?
-            .into();
+        Self::new(id, waker, pty_event_proxy_sender, settings)
+    }
+
+    /// Starts the shell again with the settings of [`Self::new`], on the
+    /// same terminal so the screen and scrollback stay. The previous shell
+    /// must have exited; with [`ExitBehavior::Hold`] pressing Enter does
+    /// this.
+    pub fn respawn(&mut self) -> std::result::Result<(), BackendError> {
+        let (Some(settings), Some(commands)) =
+            (&self.spawn_settings, &self.commands)
+        else {
+            return Err(BackendError::Io(Error::new(
+                ErrorKind::Unsupported,
+                "the backend did not spawn a shell",
+            )));
+        };
+        let Some(notifier) = &self.notifier else {
+            return Err(BackendError::Io(Error::new(
+                ErrorKind::NotConnected,
+                "the backend was shut down",
+            )));
+        };
+        if !notifier.is_closed() {
+            return Err(BackendError::Io(Error::new(
+                ErrorKind::ResourceBusy,
+                "the shell is still running",
+            )));
+        }
+
+        let source = spawn_shell(settings, self.size, self.id)?;
+        let pty_id = source.process_id().unwrap_or(0);
+        let terminal_device = source.terminal_device();
+        let (session, threads) = source::spawn(
+            self.id,
+            Box::new(source),
+            self.term.clone(),
+            self.event_proxy.clone(),
+            self.recorder.clone(),
+            self.output_bytes.clone(),
+            commands.clone(),
+            Startup::new(settings),
+        )?;
+        notifier.attach(session);
+        self.pty_id = pty_id;
+        self.terminal_device = terminal_device;
+
+        let (finished, running) = std::mem::take(&mut self.threads)
+            .into_iter()
+            .partition::<Vec<_>, _>(|thread| thread.is_finished());
+        for thread in finished {
+            let _ = thread.join();
+        }
+        self.threads = running;
+        self.threads.extend(threads);
+        Ok(())
+    }
+
+    /// Whether the session ended: the shell exited or the backend was
+    /// shut down. Always true for playback backends.
+    pub fn has_exited(&self) -> bool {
+        self.notifier.as_ref().is_none_or(Notifier::is_closed)
+    }
+
+    /// Working directory of the shell, as last reported with OSC 7 (see
+    /// [`shell_integration`]). Without reports it is read from
+    /// `/proc/<pid>/cwd` on Linux.
+    pub fn current_dir(&self) -> Option<PathBuf> {
+        let reported = self
+            .commands
+            .as_ref()
+            .and_then(|commands| commands.lock().unwrap().current_dir.clone());
+        if reported.is_some() || self.pty_id == 0 {
+            return reported;
+        }
+
+        if cfg!(target_os = "linux") {
+            std::fs::read_link(format!("/proc/{}/cwd", self.pty_id)).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Creates a backend driven by `source` instead of a spawned shell.
+    /// `shell`, `args`, `working_directory` and `env` of `settings` are not
+    /// used.
+    pub fn with_source(
+        id: u64,
+        waker: impl Into<Waker>,
+        pty_event_proxy_sender: Sender<(u64, PtyEvent)>,
+        mut source: Box<dyn BackendSource>,
+        settings: BackendSettings,
+    ) -> std::result::Result<Self, BackendError> {
+        let terminal_size = initial_terminal_size(&settings);
+        if let Err(err) = source.resize(terminal_size.into()) {
+            eprintln!("TerminalBackend::with_source(): resize failed: {err}");
+        }
+        let recording_env = settings
+            .env
+            .get("TERM")
+            .map(|term| vec![("TERM", term.clone())])
+            .unwrap_or_default();
+
+        Ok(Self::from_source(
+            id,
+            waker.into(),
+            pty_event_proxy_sender,
+            source,
+            settings,
+            terminal_size,
+            recording_env,
+        )?)
+    }
+
+    fn from_source(
+        id: u64,
+        waker: Waker,
+        pty_event_proxy_sender: Sender<(u64, PtyEvent)>,
+        source: Box<dyn BackendSource>,
+        settings: BackendSettings,
+        terminal_size: TerminalSize,
+        recording_env: Vec<(&'static str, String)>,
+    ) -> Result<Self> {
+        let (mut config, overflow) = term_config(&settings);
+        if overflow.is_none() {
+            config.scrolling_history += TRACKING_MARGIN;
+        }
+        let commands = Arc::new(Mutex::new(CommandTracker::new(
+            settings.terminal_config.scrolling_history,
+            overflow.clone(),
+        )));
+        let smart_selection_regexes = smart_selection_regexes(&settings)?;
+        let pty_id = source.process_id().unwrap_or(0);
+        let terminal_device = source.terminal_device();
+        let recorder = SharedRecorder::default();
+        let output_bytes = Arc::new(AtomicUsize::new(0));
         let (event_sender, event_receiver) = mpsc::channel();
         let event_proxy = EventProxy(event_sender);
         let mut term = Term::new(config, &terminal_size, event_proxy.clone());
-        let initial_content = RenderableContent {
-            cells: Vec::new(),
-            total_lines: term.grid().total_lines(),
-            display_offset: term.grid().display_offset(),
-            cursor_point: term.grid().cursor.point,
-            selectable_range: None,
-            terminal_mode: *term.mode(),
-            terminal_size,
-            cursor: term.grid_mut().cursor_cell().clone(),
-            hovered_hyperlink: None,
-            search_state: SearchState::default(),
-        };
+        let initial_content = initial_content(&mut term, terminal_size);
         let term = Arc::new(FairMutex::new(term));
-        let pty_event_loop =
-            EventLoop::new(term.clone(), event_proxy, pty, false, false)?;
-        let notifier = Notifier(pty_event_loop.channel());
-        let pty_notifier = Notifier(pty_event_loop.channel());
-        let url_regex = RegexSearch::new(r#"(ipfs:|ipns:|magnet:|mailto:|gemini://|gopher://|https://|http://|news:|file://|git://|ssh:|ftp://)[^\u{0000}-\u{001F}\u{007F}-\u{009F}<>"\s{-}\^⟨⟩`]+"#).unwrap();
-        let event_loop_thread = pty_event_loop.spawn();
-        let event_subscription_thread = std::thread::Builder::new()
-            .name(format!("pty_event_subscription_{}", id))
-            .spawn(move || {
-                eprintln!("pty_event_subscription_{}: started, pty_id={}", id, pty_id);
-                loop {
-                    if let Ok(event) = event_receiver.recv() {
-                        if pty_event_proxy_sender.send((id, event.clone())).is_err() {
-                            break;
-                        }
-                        app_context.clone().request_repaint();
-                        match event {
-                            Event::Exit => {
-                                eprintln!("pty_event_subscription_{}: received Exit event", id);
-                                break;
-                            }
-                            Event::PtyWrite(pty) => pty_notifier.notify(pty.into_bytes()),
-                            _ => {}
-                        }
-                    }
-                }
-                eprintln!("pty_event_subscription_{}: thread exiting", id);
-            })?;
+        let (notifier, mut threads) = source::spawn(
+            id,
+            source,
+            term.clone(),
+            event_proxy.clone(),
+            recorder.clone(),
+            output_bytes.clone(),
+            commands.clone(),
+            Startup::new(&settings),
+        )?;
+        let url_regex = RegexSearch::new(URL_REGEX).unwrap();
+        let closing = Arc::new(AtomicBool::new(false));
+        let event_subscription_thread = spawn_event_subscription(
+            id,
+            pty_id,
+            waker,
+            pty_event_proxy_sender,
+            event_receiver,
+            Some(notifier.clone()),
+            term.clone(),
+            overflow.clone(),
+            settings.exit_behavior,
+            closing.clone(),
+        )?;
+        threads.push(event_subscription_thread);
 
         Ok(Self {
             id,
             pty_id,
             url_regex,
+            smart_selection_regexes,
             term: term.clone(),
             size: terminal_size,
-            notifier,
+            notifier: Some(notifier),
+            playback: None,
+            last_content: initial_content,
+            pending_paste: None,
+            overflow,
+            overflow_offset: 0,
+            recorder,
+            commands: Some(commands),
+            output_bytes,
+            recording_env,
+            spawn_settings: None,
+            terminal_device,
+            shutdown_policy: settings.shutdown,
+            exit_behavior: settings.exit_behavior,
+            closing,
+            event_proxy,
+            threads,
+        })
+    }
+
+    /// Creates a read-only backend that replays `recording` into the
+    /// terminal. Control it with [`BackendCommand::Playback`]; it starts
+    /// paused at the beginning.
+    pub fn new_playback(
+        id: u64,
+        waker: impl Into<Waker>,
+        pty_event_proxy_sender: Sender<(u64, PtyEvent)>,
+        recording: Recording,
+        settings: BackendSettings,
+    ) -> std::result::Result<Self, BackendError> {
+        let (config, overflow) = term_config(&settings);
+        let smart_selection_regexes = smart_selection_regexes(&settings)?;
+        let terminal_size = TerminalSize {
+            num_cols: recording.columns.clamp(1, u16::MAX as usize) as u16,
+            num_lines: recording.lines.clamp(1, u16::MAX as usize) as u16,
+            ..TerminalSize::default()
+        };
+
+        let (event_sender, event_receiver) = mpsc::channel();
+        let event_proxy = EventProxy(event_sender);
+        let mut term =
+            Term::new(config.clone(), &terminal_size, event_proxy.clone());
+        let initial_content = initial_content(&mut term, terminal_size);
+        let term = Arc::new(FairMutex::new(term));
+
+        let (player, status) =
+            Player::new(recording, term.clone(), config, event_proxy.clone());
+        let (commands, command_receiver) = mpsc::channel();
+        let closing = Arc::new(AtomicBool::new(false));
+        let player_thread = std::thread::Builder::new()
+            .name(format!("playback_{}", id))
+            .spawn(move || player.run(command_receiver))?;
+        let event_subscription_thread = spawn_event_subscription(
+            id,
+            0,
+            waker.into(),
+            pty_event_proxy_sender,
+            event_receiver,
+            None,
+            term.clone(),
+            overflow.clone(),
+            ExitBehavior::Close,
+            closing.clone(),
+        )?;
+
+        Ok(Self {
+            id,
+            pty_id: 0,
+            url_regex: RegexSearch::new(URL_REGEX).unwrap(),
+            smart_selection_regexes,
+            term,
+            size: terminal_size,
+            notifier: None,
+            playback: Some(PlaybackHandle { commands, status }),
             last_content: initial_content,
-            _event_loop_thread: Some(event_subscription_thread),
-            _event_loop_thread_pty: Some(event_loop_thread),
+            pending_paste: None,
+            overflow,
+            overflow_offset: 0,
+            recorder: SharedRecorder::default(),
+            commands: None,
+            output_bytes: Arc::default(),
+            recording_env: Vec::new(),
+            spawn_settings: None,
+            terminal_device: None,
+            shutdown_policy: settings.shutdown,
+            exit_behavior: ExitBehavior::Close,
+            closing,
+            event_proxy,
+            threads: vec![player_thread, event_subscription_thread],
         })
     }
 
+    /// Ends the session on a background thread, with the signals and
+    /// grace period of [`BackendSettings::shutdown`], and waits for the
+    /// backend's threads there. Join the returned thread to wait for the
+    /// shell to exit. Dropping the backend shuts it down without waiting.
+    pub fn shutdown(&mut self) -> Result<JoinHandle<()>> {
+        let id = self.id;
+        let pid = self.pty_id;
+        let policy = self.shutdown_policy.clone();
+        let notifier = self.notifier.take();
+        let threads = std::mem::take(&mut self.threads);
+        self.closing.store(true, Ordering::Release);
+        // Stops the player thread.
+        self.playback = None;
+        std::thread::Builder::new()
+            .name(format!("shutdown_{}", id))
+            .spawn(move || shut_down(id, pid, &policy, notifier, threads))
+    }
+
+    /// Position and state of a playback backend.
+    pub fn playback_status(&self) -> Option<PlaybackStatus> {
+        let playback = self.playback.as_ref()?;
+        Some(*playback.status.lock().unwrap())
+    }
+
     pub fn process_command(&mut self, cmd: BackendCommand) {
+        if self.exit_behavior == ExitBehavior::Hold && self.has_exited() {
+            if let BackendCommand::Write(input) = &cmd {
+                if input.contains(&b'\r') {
+                    if let Err(err) = self.respawn() {
+                        eprintln!("failed to restart the shell: {err}");
+                    }
+                }
+                return;
+            }
+        }
         let term = self.term.clone();
         let mut term = term.lock();
+        self.follow_recorded_size(&term);
         match cmd {
             BackendCommand::Write(input) => {
                 self.write(input);
+                self.overflow_offset = 0;
+                if term.grid().total_lines() > term.grid().screen_lines() {
+                    term.scroll_display(Scroll::Bottom);
+                }
+            },
+            BackendCommand::Paste(text) => {
+                let bracketed = term.mode().contains(TermMode::BRACKETED_PASTE);
+                self.write(paste_payload(&text, bracketed));
+                self.overflow_offset = 0;
                 if term.grid().total_lines() > term.grid().screen_lines() {
                     term.scroll_display(Scroll::Bottom);
                 }
@@ -421,10 +787,10 @@ impl TerminalBackend {
                 self.scroll(&mut term, delta);
             },
             BackendCommand::ScrollPageUp => {
-                term.scroll_display(Scroll::PageUp);
+                self.scroll(&mut term, self.size.num_lines as i32);
             },
             BackendCommand::ScrollPageDown => {
-                term.scroll_display(Scroll::PageDown);
+                self.scroll(&mut term, -(self.size.num_lines as i32));
             },
             BackendCommand::Resize(layout_size, font_size) => {
                 self.resize(&mut term, layout_size, font_size);
@@ -435,12 +801,74 @@ impl TerminalBackend {
             BackendCommand::SelectUpdate(x, y) => {
                 self.update_selection(&mut term, x, y);
             },
+            BackendCommand::ClearSelection => {
+                term.selection = None;
+            },
             BackendCommand::ProcessLink(link_action, point) => {
                 self.process_link_action(&term, link_action, point);
             },
             BackendCommand::MouseReport(button, modifiers, point, pressed) => {
                 self.process_mouse_report(button, modifiers, point, pressed);
             },
+            BackendCommand::UpdateConfig(config) => {
+                let mut options = term::Config::from(&config);
+                match &self.overflow {
+                    Some(overflow) => {
+                        let mut store = overflow.lock().unwrap();
+                        store.hot_history = config.scrolling_history;
+                        options.scrolling_history = store.grid_history();
+                    },
+                    None => options.scrolling_history += TRACKING_MARGIN,
+                }
+                // Spill or trim through the tracker before the grid shrinks,
+                // so that no line is lost and it keeps counting the history
+                // right.
+                if let Some(commands) = &self.commands {
+                    let mut commands = commands.lock().unwrap();
+                    commands.hot_history = config.scrolling_history;
+                    commands.update(&mut term);
+                }
+                term.set_options(options);
+                if let Some(settings) = &mut self.spawn_settings {
+                    settings.terminal_config = config;
+                }
+            },
+            BackendCommand::StartRecording(settings) => {
+                if let Err(err) = self.start_recording(&settings) {
+                    eprintln!(
+                        "failed to start recording to {:?}: {err}",
+                        settings.path
+                    );
+                }
+            },
+            BackendCommand::PreviousPrompt => {
+                self.jump_to_prompt(&mut term, Direction::Left);
+            },
+            BackendCommand::NextPrompt => {
+                self.jump_to_prompt(&mut term, Direction::Right);
+            },
+            BackendCommand::SelectLastCommandOutput => {
+                self.select_command_output(&mut term, None);
+            },
+            BackendCommand::SelectCommandOutput(id) => {
+                self.select_command_output(&mut term, Some(id));
+            },
+            BackendCommand::ToggleFold(id) => {
+                self.toggle_fold(&mut term, Some(id));
+            },
+            BackendCommand::ToggleLastFold => {
+                self.toggle_fold(&mut term, None);
+            },
+            BackendCommand::StopRecording => {
+                if let Err(err) = self.stop_recording() {
+                    eprintln!("failed to finish recording: {err}");
+                }
+            },
+            BackendCommand::Playback(command) => {
+                if let Some(playback) = &self.playback {
+                    let _ = playback.commands.send(command);
+                }
+            },
         };
     }
 
@@ -527,6 +955,66 @@ impl TerminalBackend {
         result
     }
 
+    /// Exports the visible screen or the whole scrollback as plain text,
+    /// ANSI-colored text or HTML.
+    pub fn export(&self, range: ExportRange, format: &ExportFormat) -> String {
+        export_rows(&self.rows(range), format)
+    }
+
+    /// Writes [`TerminalBackend::export`] output to `path`.
+    pub fn export_to_file(
+        &self,
+        range: ExportRange,
+        format: &ExportFormat,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        std::fs::write(path, self.export(range, format))
+    }
+
+    /// Cells of the rows in `range`, overflow lines included.
+    pub(crate) fn rows(&self, range: ExportRange) -> Vec<Vec<Cell>> {
+        let term = self.term.clone();
+        let terminal = term.lock();
+        let grid = terminal.grid();
+        let row = |line: i32| -> Vec<Cell> {
+            (0..grid.columns())
+                .map(|column| grid[Line(line)][Column(column)].clone())
+                .collect()
+        };
+
+        let mut store = self.overflow.as_ref().map(|o| o.lock().unwrap());
+        let stored = store.as_ref().map_or(0, |store| store.len());
+        let mut rows = Vec::new();
+        match range {
+            ExportRange::Scrollback => {
+                if let Some(store) = &mut store {
+                    store.visit_lines(0, |_, cells| rows.push(cells.to_vec()));
+                }
+                rows.extend(
+                    (grid.topmost_line().0..=grid.bottommost_line().0).map(row),
+                );
+            },
+            ExportRange::Screen => {
+                let screen_lines = grid.screen_lines();
+                let overflow_offset = self.overflow_offset.min(stored);
+                let visible = overflow_offset.min(screen_lines);
+                if let Some(store) = &mut store {
+                    rows.extend((0..visible).map(|i| {
+                        store
+                            .line(stored - overflow_offset + i)
+                            .unwrap_or_default()
+                    }));
+                }
+                let top = -(grid.display_offset() as i32);
+                rows.extend(
+                    (top..top + (screen_lines - visible) as i32).map(row),
+                );
+            },
+        }
+
+        rows
+    }
+
     pub fn sync(&mut self) -> &RenderableContent {
         let term = self.term.clone();
         let terminal = match term.try_lock_unfair() {
@@ -536,6 +1024,7 @@ impl TerminalBackend {
             // the previous frame's content instead of waiting for the lock.
             None => return &self.last_content,
         };
+        self.follow_recorded_size(&terminal);
 
         let selectable_range = match &terminal.selection {
             Some(s) => s.to_range(&terminal),
@@ -557,14 +1046,27 @@ impl TerminalBackend {
         self.last_content.cells = cells;
         self.last_content.total_lines = grid.total_lines();
         self.last_content.display_offset = grid.display_offset();
+        if self.overflow_offset > 0 {
+            self.overlay_overflow(grid);
+        }
         self.last_content.cursor_point = cursor_point;
         self.last_content.cursor = cursor_cell;
         self.last_content.selectable_range = selectable_range;
         self.last_content.terminal_mode = *terminal.mode();
         self.last_content.terminal_size = self.size;
+        self.last_content.commands = match &self.commands {
+            Some(commands) => {
+                let commands = commands.lock().unwrap();
+                commands.visible(
+                    commands.top_line(&terminal, self.overflow_offset),
+                    terminal.screen_lines(),
+                )
+            },
+            None => Vec::new(),
+        };
 
         if self.last_content.search_state.active {
-            self.last_content.search_state.update_matches(&terminal);
+            self.update_search(&terminal);
         }
 
         self.last_content()
@@ -585,6 +1087,7 @@ impl TerminalBackend {
     pub fn scroll_to_bottom(&mut self) {
         let term = self.term.clone();
         let mut term = term.lock();
+        self.overflow_offset = 0;
         term.scroll_display(Scroll::Bottom);
     }
 
@@ -592,19 +1095,68 @@ impl TerminalBackend {
         let term = self.term.clone();
         let mut term = term.lock();
         term.scroll_display(Scroll::Top);
+        self.overflow_offset = self.overflow_line_count();
     }
 
     pub fn clear_history(&mut self) {
         let term = self.term.clone();
         let mut term = term.lock();
         term.grid_mut().clear_history();
+        if let Some(overflow) = &self.overflow {
+            overflow.lock().unwrap().clear();
+        }
+        if let Some(commands) = &self.commands {
+            commands.lock().unwrap().rebase(&term);
+        }
+        self.overflow_offset = 0;
+    }
+
+    /// Number of scrollback lines held in the overflow store, see
+    /// [`settings::OverflowSettings`].
+    pub fn overflow_line_count(&self) -> usize {
+        self.overflow
+            .as_ref()
+            .map_or(0, |overflow| overflow.lock().unwrap().len())
+    }
+
+    /// Text of the overflow line `index`, counted from the oldest stored
+    /// line.
+    pub fn overflow_line(&self, index: usize) -> Option<String> {
+        let overflow = self.overflow.as_ref()?;
+        let cells = overflow.lock().unwrap().line(index)?;
+        Some(overflow::line_text(&cells))
+    }
+
+    /// Searches only the overflow store and returns the indices of the
+    /// matching lines, oldest first. [`TerminalBackend::search_set_query`]
+    /// searches it together with the grid.
+    pub fn overflow_search(
+        &self,
+        pattern: &str,
+    ) -> std::result::Result<Vec<usize>, regex::Error> {
+        let regex = regex::Regex::new(pattern)?;
+        Ok(self
+            .overflow
+            .as_ref()
+            .map(|overflow| overflow.lock().unwrap().search(&regex))
+            .unwrap_or_default())
+    }
+
+    /// Scrolls so that the overflow line `index` is at the top of the
+    /// viewport.
+    pub fn scroll_to_overflow(&mut self, index: usize) {
+        let term = self.term.clone();
+        let mut term = term.lock();
+        term.scroll_display(Scroll::Top);
+        let len = self.overflow_line_count();
+        self.overflow_offset = len.saturating_sub(index).min(len);
     }
 
     pub fn search_set_query(&mut self, query: &str) {
         self.last_content.search_state.set_query(query);
         let term = self.term.clone();
         let term = term.lock();
-        self.last_content.search_state.update_matches(&term);
+        self.update_search(&term);
         self.last_content
             .search_state
             .set_index_from_viewport(&term);
@@ -613,7 +1165,7 @@ impl TerminalBackend {
     pub fn search_next(&mut self) -> Option<Point> {
         let term = self.term.clone();
         let term = term.lock();
-        self.last_content.search_state.update_matches(&term);
+        self.update_search(&term);
 
         if let Some(m) = self.last_content.search_state.next_match() {
             let start = *m.start();
@@ -625,7 +1177,7 @@ impl TerminalBackend {
     pub fn search_prev(&mut self) -> Option<Point> {
         let term = self.term.clone();
         let term = term.lock();
-        self.last_content.search_state.update_matches(&term);
+        self.update_search(&term);
 
         if let Some(m) = self.last_content.search_state.prev_match() {
             let start = *m.start();
@@ -634,6 +1186,17 @@ impl TerminalBackend {
         None
     }
 
+    /// Updates the search matches, the overflow store included.
+    fn update_search(&mut self, terminal: &Term<EventProxy>) {
+        let mut store = self
+            .overflow
+            .as_ref()
+            .map(|overflow| overflow.lock().unwrap());
+        self.last_content
+            .search_state
+            .update_matches_with(terminal, store.as_deref_mut());
+    }
+
     pub fn search_current_match(&self) -> Option<Point> {
         self.last_content
             .search_state
@@ -641,9 +1204,19 @@ impl TerminalBackend {
             .map(|m| *m.start())
     }
 
+    /// Scrolls `point` into view. Points above [`Term::topmost_line`], like
+    /// search matches in the overflow store, scroll into the store.
     pub fn scroll_to_point(&mut self, point: Point) {
         let term = self.term.clone();
         let mut term = term.lock();
+        let topmost = term.topmost_line();
+        if point.line < topmost {
+            term.scroll_display(Scroll::Top);
+            let above = (topmost.0 - point.line.0) as usize;
+            self.overflow_offset = above.min(self.overflow_line_count());
+            return;
+        }
+        self.overflow_offset = 0;
         let display_offset = term.grid().display_offset();
         let viewport_top = -(display_offset as i32);
         let viewport_bottom = viewport_top + (self.size.num_lines as i32 - 1);
@@ -658,6 +1231,56 @@ impl TerminalBackend {
         }
     }
 
+    /// Starts writing an asciicast v2 recording of the session, replacing a
+    /// running one.
+    pub fn start_recording(
+        &mut self,
+        settings: &RecordingSettings,
+    ) -> Result<()> {
+        self.stop_recording()?;
+        let recorder = AsciicastRecorder::create(
+            settings,
+            self.size.num_cols as usize,
+            self.size.num_lines as usize,
+            &self.recording_env,
+        )?;
+        *self.recorder.lock().unwrap() = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recorder.lock().unwrap().take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder
+            .lock()
+            .is_ok_and(|recorder| recorder.is_some())
+    }
+
+    /// Paste that is waiting for the host to confirm it, see
+    /// [`crate::PastePolicy`].
+    pub fn pending_paste(&self) -> Option<&PasteRequest> {
+        self.pending_paste.as_ref()
+    }
+
+    pub fn accept_pending_paste(&mut self) {
+        if let Some(request) = self.pending_paste.take() {
+            self.process_command(BackendCommand::Paste(request.text));
+        }
+    }
+
+    pub fn reject_pending_paste(&mut self) {
+        self.pending_paste = None;
+    }
+
+    pub(crate) fn set_pending_paste(&mut self, request: PasteRequest) {
+        self.pending_paste = Some(request);
+    }
+
     pub fn search_active(&self) -> bool {
         self.last_content.search_state.active
     }
@@ -774,7 +1397,7 @@ impl TerminalBackend {
             c
         );
 
-        self.notifier.notify(msg.as_bytes().to_vec());
+        self.write(msg.into_bytes());
     }
 
     fn normal_mouse_report(&self, point: Point, button: u8, is_utf8: bool) {
@@ -806,7 +1429,7 @@ impl TerminalBackend {
             msg.push(32 + 1 + line.0 as u8);
         }
 
-        self.notifier.notify(msg);
+        self.write(msg);
     }
 
     fn start_selection(
@@ -816,12 +1439,25 @@ impl TerminalBackend {
         x: f32,
         y: f32,
     ) {
-        let location = Self::selection_point(
-            x,
-            y,
-            &self.size,
-            terminal.grid().display_offset(),
-        );
+        let location = self.viewport_point(terminal, x, y);
+        if location.line < terminal.topmost_line() {
+            // Overflow rows are not part of the grid and cannot be selected.
+            terminal.selection = None;
+            return;
+        }
+        if selection_type == SelectionType::Semantic {
+            if let Some(range) = self.smart_selection_at(terminal, location) {
+                let mut selection = Selection::new(
+                    SelectionType::Simple,
+                    *range.start(),
+                    Side::Left,
+                );
+                selection.update(*range.end(), Side::Right);
+                terminal.selection = Some(selection);
+                return;
+            }
+        }
+
         terminal.selection = Some(Selection::new(
             selection_type,
             location,
@@ -829,20 +1465,47 @@ impl TerminalBackend {
         ));
     }
 
+    /// Finds the first smart selection pattern match under `point`.
+    fn smart_selection_at(
+        &mut self,
+        terminal: &Term<EventProxy>,
+        point: Point,
+    ) -> Option<Match> {
+        self.smart_selection_regexes
+            .iter_mut()
+            .find_map(|regex| regex_match_at(terminal, point, regex))
+    }
+
     fn update_selection(
         &mut self,
         terminal: &mut Term<EventProxy>,
         x: f32,
         y: f32,
     ) {
-        let display_offset = terminal.grid().display_offset();
+        let location = self.viewport_point(terminal, x, y);
+        let topmost = Point::new(terminal.topmost_line(), Column(0));
         if let Some(ref mut selection) = terminal.selection {
-            let location =
-                Self::selection_point(x, y, &self.size, display_offset);
-            selection.update(location, self.selection_side(x));
+            if location < topmost {
+                selection.update(topmost, Side::Left);
+            } else {
+                selection.update(location, self.selection_side(x));
+            }
         }
     }
 
+    /// Point under `x`, `y`, counting the overflow rows shown on top of the
+    /// grid. Those rows are above [`Term::topmost_line`].
+    fn viewport_point(
+        &self,
+        terminal: &Term<EventProxy>,
+        x: f32,
+        y: f32,
+    ) -> Point {
+        let display_offset =
+            terminal.grid().display_offset() + self.overflow_offset;
+        Self::selection_point(x, y, &self.size, display_offset)
+    }
+
     fn selection_side(&self, x: f32) -> Side {
         let cell_x = x as usize % self.size.cell_width as usize;
         let half_cell_width = (self.size.cell_width as f32 / 2.0) as usize;
@@ -854,6 +1517,15 @@ impl TerminalBackend {
         }
     }
 
+    /// The player applies the resizes of a recording to the terminal, the
+    /// grid size follows them.
+    fn follow_recorded_size(&mut self, terminal: &Term<EventProxy>) {
+        if self.playback.is_some() {
+            self.size.num_lines = terminal.screen_lines() as u16;
+            self.size.num_cols = terminal.columns() as u16;
+        }
+    }
+
     fn resize(
         &mut self,
         terminal: &mut Term<EventProxy>,
@@ -870,32 +1542,210 @@ impl TerminalBackend {
         let lines = (layout_size.height / font_size.height.floor()) as u16;
         let cols = (layout_size.width / font_size.width.floor()) as u16;
         if lines > 0 && cols > 0 {
-            self.size = TerminalSize {
-                layout_size,
-                cell_height: font_size.height as u16,
-                cell_width: font_size.width as u16,
-                num_lines: lines,
-                num_cols: cols,
+            self.size.layout_size = layout_size;
+            self.size.cell_height = font_size.height as u16;
+            self.size.cell_width = font_size.width as u16;
+
+            // A recording keeps the size it was made with, only the cell
+            // metrics follow the view.
+            let Some(notifier) = &self.notifier else {
+                return;
             };
-
-            self.notifier.on_resize(self.size.into());
+            self.size.num_lines = lines;
+            self.size.num_cols = cols;
+            notifier.on_resize(self.size.into());
+            record(&self.recorder, |recorder| {
+                recorder.resize(cols as usize, lines as usize)
+            });
+            let folded = self
+                .commands
+                .as_ref()
+                .map(|commands| commands.lock().unwrap().unfold_grid(terminal));
             terminal.resize(TermSize::new(
                 self.size.num_cols as usize,
                 self.size.num_lines as usize,
             ));
+            if let Some(commands) = &self.commands {
+                let mut commands = commands.lock().unwrap();
+                commands.rebase(terminal);
+                commands.refold(&folded.unwrap_or_default(), terminal);
+            }
         }
     }
 
+    /// Scrolls the closest prompt above or below the top of the viewport
+    /// to the top.
+    fn jump_to_prompt(
+        &mut self,
+        terminal: &mut Term<EventProxy>,
+        direction: Direction,
+    ) {
+        let Some(commands) = &self.commands else {
+            return;
+        };
+        let commands = commands.lock().unwrap();
+        let top_line = commands.top_line(terminal, self.overflow_offset);
+        let target = match direction {
+            Direction::Left => commands.previous_prompt(top_line),
+            Direction::Right => commands.next_prompt(top_line),
+        };
+        let Some(target) = target else {
+            return;
+        };
+
+        if let Some(index) = commands.overflow_index(target) {
+            terminal.scroll_display(Scroll::Top);
+            self.overflow_offset =
+                self.overflow_line_count().saturating_sub(index);
+            return;
+        }
+        let point = MarkPoint {
+            line: target,
+            column: Column(0),
+        };
+        if let Some(point) = commands.grid_point(terminal, point) {
+            self.overflow_offset = 0;
+            let offset = (-point.line.0).max(0) as usize;
+            let delta = offset as i32 - terminal.grid().display_offset() as i32;
+            terminal.scroll_display(Scroll::Delta(delta));
+        }
+    }
+
+    /// Folds or unfolds the output of the command `id`, or of the last
+    /// finished command.
+    fn toggle_fold(
+        &mut self,
+        terminal: &mut Term<EventProxy>,
+        id: Option<u64>,
+    ) {
+        let Some(commands) = &self.commands else {
+            return;
+        };
+        let mut commands = commands.lock().unwrap();
+        let Some(id) = id.or_else(|| commands.last_finished()) else {
+            return;
+        };
+        commands.toggle_fold(id, terminal);
+        // Lines moved, a selection would point at other text now.
+        terminal.selection = None;
+    }
+
+    /// Selects the output of the command `id`, or of the last finished
+    /// command, as far as it is still in the grid.
+    fn select_command_output(
+        &mut self,
+        terminal: &mut Term<EventProxy>,
+        id: Option<u64>,
+    ) {
+        let Some(commands) = &self.commands else {
+            return;
+        };
+        let commands = commands.lock().unwrap();
+        let output = match id {
+            Some(id) => commands.output(id),
+            None => commands.last_output(),
+        };
+        let Some((start, end)) = output else {
+            return;
+        };
+
+        let topmost = Point::new(terminal.grid().topmost_line(), Column(0));
+        let start = commands.grid_point(terminal, start).unwrap_or(topmost);
+        let Some(end) = commands.grid_point(terminal, end) else {
+            return;
+        };
+        // The output ends right before the mark, usually at the end of the
+        // previous line, where the trailing blanks are left out.
+        let mut end = end.sub(terminal, Boundary::Grid, 1);
+        if end.column == terminal.last_column() {
+            let length = terminal.grid()[end.line].line_length();
+            end.column = Column(length.0.saturating_sub(1));
+        }
+        if end < start {
+            return;
+        }
+
+        let mut selection =
+            Selection::new(AlacrittySelectionType::Simple, start, Side::Left);
+        selection.update(end, Side::Right);
+        terminal.selection = Some(selection);
+    }
+
     fn write<I: Into<Cow<'static, [u8]>>>(&self, input: I) {
-        self.notifier.notify(input);
+        let input = input.into();
+        if let Some(notifier) = &self.notifier {
+            record(&self.recorder, |recorder| recorder.input(&input));
+            notifier.notify(input);
+        }
     }
 
     fn scroll(&mut self, terminal: &mut Term<EventProxy>, delta_value: i32) {
-        if delta_value != 0 {
-            let scroll = Scroll::Delta(delta_value);
-            terminal.grid_mut().scroll_display(scroll);
+        if delta_value == 0 {
+            return;
+        }
+
+        // Scrolling down leaves the overflow store first, scrolling up only
+        // enters it once the grid history is exhausted.
+        let mut delta = delta_value;
+        if delta < 0 && self.overflow_offset > 0 {
+            let consumed =
+                self.overflow_offset.min(delta.unsigned_abs() as usize);
+            self.overflow_offset -= consumed;
+            delta += consumed as i32;
+        }
+
+        let grid = terminal.grid_mut();
+        let before = grid.display_offset();
+        grid.scroll_display(Scroll::Delta(delta));
+        let remaining = delta - (grid.display_offset() as i32 - before as i32);
+        if remaining > 0 && !terminal.mode().contains(TermMode::ALT_SCREEN) {
+            self.overflow_offset = (self.overflow_offset + remaining as usize)
+                .min(self.overflow_line_count());
         }
     }
+
+    /// Shifts the rendered grid rows down by `overflow_offset` and fills the
+    /// top of the viewport with lines from the overflow store.
+    fn overlay_overflow(
+        &mut self,
+        grid: &alacritty_terminal::grid::Grid<Cell>,
+    ) {
+        let Some(overflow) = &self.overflow else {
+            return;
+        };
+        let mut store = overflow.lock().unwrap();
+        let len = store.len();
+        self.overflow_offset = self.overflow_offset.min(len);
+
+        let screen_lines = grid.screen_lines();
+        let display_offset = grid.display_offset() + self.overflow_offset;
+        let top = -(display_offset as i32);
+        let visible = self.overflow_offset.min(screen_lines);
+        let grid_bottom = top + screen_lines as i32 - 1;
+
+        let mut cells = Vec::with_capacity(self.last_content.cells.len());
+        for row in 0..visible {
+            let line = Line(top + row as i32);
+            let stored = store
+                .line(len - self.overflow_offset + row)
+                .unwrap_or_default();
+            for column in 0..grid.columns() {
+                cells.push(RenderableCell {
+                    point: Point::new(line, Column(column)),
+                    cell: stored.get(column).cloned().unwrap_or_default(),
+                });
+            }
+        }
+        cells.extend(
+            std::mem::take(&mut self.last_content.cells)
+                .into_iter()
+                .filter(|indexed| indexed.point.line.0 <= grid_bottom),
+        );
+
+        self.last_content.cells = cells;
+        self.last_content.total_lines += len;
+        self.last_content.display_offset = display_offset;
+    }
 }
 
 /// Based on alacritty/src/display/hint.rs > regex_match_at
@@ -927,6 +1777,247 @@ fn visible_regex_match_iter<'a>(
         .take_while(move |rm| rm.start().line <= viewport_end)
 }
 
+/// Starts the shell of backend `id` on a fresh pty.
+fn spawn_shell(
+    settings: &BackendSettings,
+    terminal_size: TerminalSize,
+    id: u64,
+) -> std::result::Result<PtySource, SpawnError> {
+    let spawn_error = |error| SpawnError {
+        shell: settings.shell.clone(),
+        error,
+    };
+    #[cfg(unix)]
+    let source = PtySource::spawn_command(
+        settings.shell_command(id),
+        terminal_size.into(),
+    );
+    #[cfg(windows)]
+    let source = {
+        if settings.login_shell {
+            return Err(spawn_error(Error::new(
+                ErrorKind::Unsupported,
+                "login shells are not supported on Windows",
+            )));
+        }
+        // ConPTY always passes the whole environment on.
+        let policy = &settings.env_policy;
+        if policy.inherit != settings::EnvInherit::All
+            || !policy.unset.is_empty()
+        {
+            return Err(spawn_error(Error::new(
+                ErrorKind::Unsupported,
+                "the inherited environment cannot be restricted on Windows",
+            )));
+        }
+        let pty_config = tty::Options {
+            shell: Some(tty::Shell::new(
+                settings.shell.clone(),
+                settings.args.clone(),
+            )),
+            working_directory: settings.working_directory.clone(),
+            env: settings.env_policy.variables(&settings.env, id),
+            ..tty::Options::default()
+        };
+        PtySource::spawn(&pty_config, terminal_size.into(), id)
+    };
+    source.map_err(spawn_error)
+}
+
+/// Grid size from the layout and cell size hints of `settings`.
+fn initial_terminal_size(settings: &BackendSettings) -> TerminalSize {
+    match (settings.initial_layout_size, settings.initial_cell_metrics) {
+        (Some(layout), Some(cell)) if cell.width > 0.0 && cell.height > 0.0 => {
+            let lines = (layout.height / cell.height.floor()) as u16;
+            let cols = (layout.width / cell.width.floor()) as u16;
+            if lines > 0 && cols > 0 {
+                TerminalSize {
+                    layout_size: layout,
+                    cell_width: cell.width as u16,
+                    cell_height: cell.height as u16,
+                    num_lines: lines,
+                    num_cols: cols,
+                }
+            } else {
+                TerminalSize::default()
+            }
+        },
+        _ => TerminalSize::default(),
+    }
+}
+
+/// Terminal options and the overflow store for `settings`. With an
+/// overflow store the grid keeps a spill margin on top of the history.
+fn term_config(
+    settings: &BackendSettings,
+) -> (term::Config, Option<Arc<Mutex<OverflowStore>>>) {
+    let mut config = term::Config::from(&settings.terminal_config);
+    let overflow = settings.overflow.clone().map(|overflow| {
+        let store = OverflowStore::new(
+            overflow,
+            settings.terminal_config.scrolling_history,
+        );
+        config.scrolling_history = store.grid_history();
+        Arc::new(Mutex::new(store))
+    });
+
+    (config, overflow)
+}
+
+fn smart_selection_regexes(
+    settings: &BackendSettings,
+) -> Result<Vec<RegexSearch>> {
+    if !settings.smart_selection {
+        return Ok(Vec::new());
+    }
+
+    settings
+        .smart_selection_patterns
+        .iter()
+        .map(|pattern| {
+            RegexSearch::new(pattern).map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "invalid smart selection pattern {pattern:?}: {err}"
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+fn initial_content(
+    term: &mut Term<EventProxy>,
+    terminal_size: TerminalSize,
+) -> RenderableContent {
+    RenderableContent {
+        cells: Vec::new(),
+        total_lines: term.grid().total_lines(),
+        display_offset: term.grid().display_offset(),
+        cursor_point: term.grid().cursor.point,
+        selectable_range: None,
+        terminal_mode: *term.mode(),
+        terminal_size,
+        cursor: term.grid_mut().cursor_cell().clone(),
+        hovered_hyperlink: None,
+        search_state: SearchState::default(),
+        commands: Vec::new(),
+    }
+}
+
+/// Forwards terminal events to the host, wakes it up, answers
+/// terminal queries through `pty_notifier` and spills overflow lines.
+#[allow(clippy::too_many_arguments)]
+fn spawn_event_subscription(
+    id: u64,
+    pty_id: u32,
+    waker: Waker,
+    pty_event_proxy_sender: Sender<(u64, PtyEvent)>,
+    event_receiver: mpsc::Receiver<PtyEvent>,
+    pty_notifier: Option<Notifier>,
+    term: Arc<FairMutex<Term<EventProxy>>>,
+    overflow: Option<Arc<Mutex<OverflowStore>>>,
+    exit_behavior: ExitBehavior,
+    closing: Arc<AtomicBool>,
+) -> Result<std::thread::JoinHandle<()>> {
+    std::thread::Builder::new()
+        .name(format!("pty_event_subscription_{}", id))
+        .spawn(move || {
+            eprintln!(
+                "pty_event_subscription_{}: started, pty_id={}",
+                id, pty_id
+            );
+            // Sessions started by `respawn` send to the same channel, so
+            // this runs until the backend shuts down.
+            let mut exit_status = None;
+            loop {
+                let event =
+                    match event_receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+                        Ok(event) => event,
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            let ended = pty_notifier
+                                .as_ref()
+                                .is_none_or(|notifier| notifier.is_closed());
+                            if closing.load(Ordering::Acquire) && ended {
+                                break;
+                            }
+                            continue;
+                        },
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    };
+                let closing = closing.load(Ordering::Acquire);
+                match &event {
+                    PtyEvent::ChildExit(status) => exit_status = Some(*status),
+                    PtyEvent::Exit
+                        if exit_behavior == ExitBehavior::Hold && !closing =>
+                    {
+                        hold_after_exit(&mut term.lock(), exit_status.take());
+                        waker.wake();
+                        continue;
+                    },
+                    _ => {},
+                }
+                if pty_event_proxy_sender.send((id, event.clone())).is_err() {
+                    break;
+                }
+                waker.wake();
+                match event {
+                    PtyEvent::Exit => {
+                        eprintln!(
+                            "pty_event_subscription_{}: received Exit event",
+                            id
+                        );
+                        if closing {
+                            break;
+                        }
+                    },
+                    PtyEvent::PtyWrite(pty) => {
+                        if let Some(notifier) = &pty_notifier {
+                            notifier.notify(pty.into_bytes());
+                        }
+                    },
+                    PtyEvent::Wakeup => {
+                        if let Some(overflow) = &overflow {
+                            let mut term = term.lock();
+                            if let Ok(mut store) = overflow.lock() {
+                                store.spill(&mut term);
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            eprintln!("pty_event_subscription_{}: thread exiting", id);
+        })
+}
+
+/// Leaves the terminal in a usable state after its program exited and
+/// prints the banner of [`ExitBehavior::Hold`].
+fn hold_after_exit(term: &mut Term<EventProxy>, status: Option<ExitStatus>) {
+    let mut parser: Processor = Processor::new();
+    parser.advance(term, EXIT_RESET.as_bytes());
+
+    let how = match status {
+        Some(ExitStatus {
+            code: Some(code), ..
+        }) => format!(" with status {code}"),
+        Some(ExitStatus {
+            signal: Some(signal),
+            ..
+        }) => format!(" with {signal}"),
+        _ => String::new(),
+    };
+    let mut banner = String::new();
+    if term.grid().cursor.point.column.0 > 0 {
+        banner.push_str("\r\n");
+    }
+    banner.push_str(&format!(
+        "\x1b[1m[process exited{how} — press Enter to restart]\x1b[0m\r\n"
+    ));
+    parser.advance(term, banner.as_bytes());
+}
+
 pub struct RenderableCell {
     pub point: Point,
     pub cell: Cell,
@@ -943,6 +2034,8 @@ pub struct RenderableContent {
     pub terminal_mode: TermMode,
     pub terminal_size: TerminalSize,
     pub search_state: SearchState,
+    /// Shell commands on screen, when the shell emits OSC 133 marks.
+    pub commands: Vec<CommandInfo>,
 }
 
 impl Default for RenderableContent {
@@ -958,39 +2051,63 @@ impl Default for RenderableContent {
             terminal_mode: TermMode::empty(),
             terminal_size: TerminalSize::default(),
             search_state: SearchState::default(),
+            commands: Vec::new(),
         }
     }
 }
 
 impl Drop for TerminalBackend {
     fn drop(&mut self) {
-        eprintln!("TerminalBackend::drop(): killing pid={}", self.pty_id);
-        #[cfg(unix)]
-        unsafe {
-            let pid = self.pty_id as i32;
-            let _ = libc::kill(-pid, libc::SIGHUP);
-            let _ = libc::kill(-pid, libc::SIGKILL);
-        }
-        #[cfg(windows)]
-        unsafe {
-            use windows_sys::Win32::Foundation::CloseHandle;
-            use windows_sys::Win32::System::Threading::{
-                OpenProcess, TerminateProcess, PROCESS_TERMINATE,
-            };
-            let handle = OpenProcess(PROCESS_TERMINATE, 0, self.pty_id);
-            if !handle.is_null() {
-                let _ = TerminateProcess(handle, 1);
-                let _ = CloseHandle(handle);
-            }
+        if self.notifier.is_none() && self.threads.is_empty() {
+            return;
+        }
+        if let Err(err) = self.shutdown() {
+            eprintln!("TerminalBackend::drop(): shutdown failed: {err}");
         }
     }
 }
 
-#[derive(Clone)]
-pub struct EventProxy(mpsc::Sender<Event>);
+/// Sends the signals of `policy` to process group `pid` until the source
+/// closed, then gives `threads` one grace period to finish.
+fn shut_down(
+    id: u64,
+    pid: u32,
+    policy: &ShutdownPolicy,
+    notifier: Option<Notifier>,
+    threads: Vec<JoinHandle<()>>,
+) {
+    let wait_until = |done: &dyn Fn() -> bool, deadline: Instant| {
+        while !done() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    if let Some(notifier) = notifier {
+        for &signal in policy.signals.iter().filter(|_| pid != 0) {
+            if notifier.is_closed() {
+                break;
+            }
+            if let Err(err) = process::kill(pid, true, signal) {
+                eprintln!("shutdown_{}: {:?} failed: {err}", id, signal);
+            }
+            wait_until(&|| notifier.is_closed(), Instant::now() + policy.grace);
+        }
+        notifier.shutdown();
+    }
 
-impl EventListener for EventProxy {
-    fn send_event(&self, event: Event) {
-        let _ = self.0.send(event.clone());
+    // Releasing the source stops its threads, the deadline only guards
+    // against sources that cannot interrupt their reader.
+    let deadline = Instant::now() + policy.grace;
+    for thread in threads {
+        wait_until(&|| thread.is_finished(), deadline);
+        if thread.is_finished() {
+            let _ = thread.join();
+        } else {
+            eprintln!(
+                "shutdown_{}: leaving {} behind",
+                id,
+                thread.thread().name().unwrap_or("thread")
+            );
+        }
     }
 }
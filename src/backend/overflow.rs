@@ -0,0 +1,1308 @@
+//! Compressed store for scrollback lines that no longer fit into the
+//! alacritty grid.
+//!
+//! Lines are encoded cell by cell, with their combining characters,
+//! hyperlink and underline colour, grouped into chunks of
+//! [`CHUNK_LINES`] and deflated once a chunk is full. Chunks are appended to
+//! segments that live either in memory or in files next to the configured
+//! path. When the compressed size exceeds the budget the oldest segment is
+//! dropped as a whole.
+//!
+//! Stored lines are never rewritten. Folding command output that left the
+//! grid splices a summary over them instead, and lines are indexed as
+//! shown, with the splices applied.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use alacritty_terminal::grid::{Dimensions, Row};
+use alacritty_terminal::index::{Column, Line, Point};
+use alacritty_terminal::term::cell::{Cell, Flags, Hyperlink};
+use alacritty_terminal::term::search::Match;
+use alacritty_terminal::term::{Term, TermMode};
+use alacritty_terminal::vte::ansi::{Color, NamedColor, Rgb};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use regex::Regex;
+
+use super::settings::{OverflowSettings, OverflowStorage};
+
+const CHUNK_LINES: usize = 256;
+const MIN_SEGMENT_BYTES: usize = 64 * 1024;
+const CACHED_CHUNKS: usize = 4;
+
+/// Bits telling which optional parts follow an encoded cell.
+const HAS_ZEROWIDTH: u8 = 1;
+const HAS_UNDERLINE_COLOR: u8 = 2;
+const HAS_HYPERLINK: u8 = 4;
+
+const NAMED_COLORS: [NamedColor; 29] = [
+    NamedColor::Black,
+    NamedColor::Red,
+    NamedColor::Green,
+    NamedColor::Yellow,
+    NamedColor::Blue,
+    NamedColor::Magenta,
+    NamedColor::Cyan,
+    NamedColor::White,
+    NamedColor::BrightBlack,
+    NamedColor::BrightRed,
+    NamedColor::BrightGreen,
+    NamedColor::BrightYellow,
+    NamedColor::BrightBlue,
+    NamedColor::BrightMagenta,
+    NamedColor::BrightCyan,
+    NamedColor::BrightWhite,
+    NamedColor::Foreground,
+    NamedColor::Background,
+    NamedColor::Cursor,
+    NamedColor::DimBlack,
+    NamedColor::DimRed,
+    NamedColor::DimGreen,
+    NamedColor::DimYellow,
+    NamedColor::DimBlue,
+    NamedColor::DimMagenta,
+    NamedColor::DimCyan,
+    NamedColor::DimWhite,
+    NamedColor::BrightForeground,
+    NamedColor::DimForeground,
+];
+
+struct Chunk {
+    /// Absolute number of the first line in the chunk. Chunks are ordered
+    /// by it, so it doubles as the index [`OverflowStore::locate`]
+    /// searches.
+    first_line: u64,
+    lines: usize,
+    offset: u64,
+    len: usize,
+}
+
+enum SegmentData {
+    Memory(Vec<u8>),
+    File { file: File, path: PathBuf },
+}
+
+struct Segment {
+    data: SegmentData,
+    chunks: Vec<Chunk>,
+    bytes: usize,
+}
+
+impl Segment {
+    fn read_chunk(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let chunk = &self.chunks[index];
+        let compressed = match &mut self.data {
+            SegmentData::Memory(data) => {
+                let start = chunk.offset as usize;
+                data[start..start + chunk.len].to_vec()
+            },
+            SegmentData::File { file, .. } => {
+                let mut buf = vec![0; chunk.len];
+                file.seek(SeekFrom::Start(chunk.offset))?;
+                file.read_exact(&mut buf)?;
+                buf
+            },
+        };
+
+        let mut decoded = Vec::new();
+        DeflateDecoder::new(compressed.as_slice()).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        if let SegmentData::File { path, .. } = &self.data {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+pub(crate) struct OverflowStore {
+    settings: OverflowSettings,
+    /// Scrollback size the user asked for. The grid itself keeps
+    /// `hot_history + spill_margin` lines so nothing is dropped between two
+    /// spills.
+    pub(crate) hot_history: usize,
+    segments: VecDeque<Segment>,
+    next_segment_id: usize,
+    /// Absolute number of the oldest line still stored.
+    base_line: u64,
+    /// Number of stored lines, sealed or open.
+    stored_len: usize,
+    /// Encoded lines not sealed into a chunk yet. Grows past
+    /// [`CHUNK_LINES`] while chunks cannot be stored.
+    open_chunk: Vec<Vec<u8>>,
+    /// Set while storing chunks fails, to log the failure once.
+    failing: bool,
+    cache: VecDeque<(u64, Vec<Vec<Cell>>)>,
+    /// Folded command output, ordered by the stored lines it covers.
+    splices: Vec<Splice>,
+    /// Number of the oldest line as shown, counted like `base_line` but
+    /// with the splices applied.
+    first_line: u64,
+    /// Bumped whenever splices change the lines shown.
+    generation: u64,
+}
+
+/// Stored lines of command `id` shown differently while its output is
+/// folded.
+struct Splice {
+    id: u64,
+    /// Absolute number of the first stored line covered.
+    start: u64,
+    /// Number of stored lines covered.
+    len: usize,
+    /// Shown instead of the covered lines while folded. `None` hides them
+    /// when the summary is still in the grid.
+    summary: Option<Vec<Cell>>,
+    folded: bool,
+    /// Output of a summary that was folded in the grid and spilled into
+    /// the store afterwards, shown once unfolded in place of the given
+    /// number of last covered lines: the summary and the lines the fold
+    /// blanked above it.
+    tail: Option<(usize, Vec<Vec<Cell>>)>,
+}
+
+impl Splice {
+    /// Number of lines shown for the covered ones.
+    fn shown(&self) -> usize {
+        if self.folded {
+            usize::from(self.summary.is_some())
+        } else {
+            self.tail.as_ref().map_or(self.len, |(replaced, rows)| {
+                self.len - replaced + rows.len()
+            })
+        }
+    }
+}
+
+/// Where a shown line comes from.
+enum Source<'a> {
+    Stored(u64),
+    Spliced(&'a [Cell]),
+}
+
+impl OverflowStore {
+    pub(crate) fn new(settings: OverflowSettings, hot_history: usize) -> Self {
+        Self {
+            settings,
+            hot_history,
+            segments: VecDeque::new(),
+            next_segment_id: 0,
+            base_line: 0,
+            stored_len: 0,
+            open_chunk: Vec::new(),
+            failing: false,
+            cache: VecDeque::new(),
+            splices: Vec::new(),
+            first_line: 0,
+            generation: 0,
+        }
+    }
+
+    pub(crate) fn grid_history(&self) -> usize {
+        self.hot_history + self.settings.spill_margin
+    }
+
+    /// Number of lines shown, with folded output counted as its summary.
+    pub(crate) fn len(&self) -> usize {
+        let spliced = self
+            .splices
+            .iter()
+            .map(|splice| splice.shown() as isize - splice.len as isize)
+            .sum::<isize>();
+        self.stored_len.saturating_add_signed(spliced)
+    }
+
+    /// Absolute number of the first line not sealed into a chunk yet.
+    fn open_line(&self) -> u64 {
+        self.base_line + (self.stored_len - self.open_chunk.len()) as u64
+    }
+
+    /// Moves the grid lines above `hot_history` into the store.
+    pub(crate) fn spill<T>(&mut self, term: &mut Term<T>) {
+        // The alternate screen has no scrollback of its own.
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            return;
+        }
+
+        let grid = term.grid_mut();
+        let history = grid.history_size();
+        if history <= self.hot_history {
+            return;
+        }
+
+        let columns = grid.columns();
+        for line in (self.hot_history + 1..=history).rev() {
+            self.push_line(&grid[Line(-(line as i32))], columns);
+        }
+        grid.update_history(self.hot_history);
+        grid.update_history(self.grid_history());
+    }
+
+    fn push_line(&mut self, row: &Row<Cell>, columns: usize) {
+        let mut encoded = Vec::new();
+        let used = (0..columns)
+            .rev()
+            .find(|&column| !is_blank(&row[Column(column)]))
+            .map_or(0, |column| column + 1);
+        write_varint(&mut encoded, used as u64);
+        for column in 0..used {
+            encode_cell(&mut encoded, &row[Column(column)]);
+        }
+
+        self.open_chunk.push(encoded);
+        self.stored_len += 1;
+        // After a failure, try again every chunk's worth of lines.
+        if self.open_chunk.len().is_multiple_of(CHUNK_LINES) {
+            self.seal_chunks();
+        }
+    }
+
+    /// Seals the full chunks at the front of the open one. Lines that
+    /// cannot be stored stay open and are sealed on a later try.
+    fn seal_chunks(&mut self) {
+        while self.open_chunk.len() >= CHUNK_LINES {
+            if let Err(err) = self.seal_chunk(CHUNK_LINES) {
+                if !self.failing {
+                    eprintln!(
+                        "failed to store scrollback overflow, keeping it \
+                         uncompressed: {err}"
+                    );
+                }
+                self.failing = true;
+                return;
+            }
+            self.failing = false;
+        }
+    }
+
+    /// Stores the first `count` open lines as a chunk.
+    fn seal_chunk(&mut self, count: usize) -> io::Result<()> {
+        let first_line = self.open_line();
+
+        let mut raw = Vec::new();
+        for line in &self.open_chunk[..count] {
+            write_varint(&mut raw, line.len() as u64);
+            raw.extend_from_slice(line);
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&raw)?;
+        let compressed = encoder.finish()?;
+
+        let segment_bytes =
+            (self.settings.budget_bytes / 4).max(MIN_SEGMENT_BYTES);
+        if self
+            .segments
+            .back()
+            .is_none_or(|segment| segment.bytes >= segment_bytes)
+        {
+            let segment = self.new_segment()?;
+            self.segments.push_back(segment);
+        }
+
+        let segment = self.segments.back_mut().expect("segment exists");
+        let offset = match &mut segment.data {
+            SegmentData::Memory(data) => {
+                data.extend_from_slice(&compressed);
+                (data.len() - compressed.len()) as u64
+            },
+            SegmentData::File { file, .. } => {
+                let offset = file.seek(SeekFrom::End(0))?;
+                file.write_all(&compressed)?;
+                offset
+            },
+        };
+        segment.bytes += compressed.len();
+        segment.chunks.push(Chunk {
+            first_line,
+            lines: count,
+            offset,
+            len: compressed.len(),
+        });
+        self.open_chunk.drain(..count);
+
+        self.evict();
+        Ok(())
+    }
+
+    fn new_segment(&mut self) -> io::Result<Segment> {
+        let id = self.next_segment_id;
+
+        let data = match &self.settings.storage {
+            OverflowStorage::Memory => SegmentData::Memory(Vec::new()),
+            OverflowStorage::File(base) => {
+                let mut path = base.clone().into_os_string();
+                path.push(format!(".{id}"));
+                let path = PathBuf::from(path);
+                let file = OpenOptions::new()
+                    .create(true)
+                    .truncate(true)
+                    .read(true)
+                    .write(true)
+                    .open(&path)?;
+                SegmentData::File { file, path }
+            },
+        };
+        self.next_segment_id += 1;
+
+        Ok(Segment {
+            data,
+            chunks: Vec::new(),
+            bytes: 0,
+        })
+    }
+
+    fn evict(&mut self) {
+        let shown = self.len();
+        let mut total: usize = self.segments.iter().map(|s| s.bytes).sum();
+        while total > self.settings.budget_bytes && self.segments.len() > 1 {
+            let segment = self.segments.pop_front().expect("segment exists");
+            total -= segment.bytes;
+            let lines: usize = segment.chunks.iter().map(|c| c.lines).sum();
+            self.base_line += lines as u64;
+            self.stored_len -= lines;
+        }
+        let base_line = self.base_line;
+        self.cache
+            .retain(|(first_line, _)| *first_line >= base_line);
+        // Splices keep what is left of their lines.
+        self.splices
+            .retain(|splice| splice.start + splice.len as u64 > base_line);
+        for splice in &mut self.splices {
+            if splice.start < base_line {
+                splice.len -= (base_line - splice.start) as usize;
+                splice.start = base_line;
+            }
+        }
+        self.first_line += (shown - self.len()) as u64;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.first_line += self.len() as u64;
+        self.base_line += self.stored_len as u64;
+        self.stored_len = 0;
+        self.segments.clear();
+        self.open_chunk.clear();
+        self.cache.clear();
+        self.splices.clear();
+    }
+
+    /// Number of the oldest line shown, increasing as lines are evicted.
+    pub(crate) fn first_line(&self) -> u64 {
+        self.first_line
+    }
+
+    /// Changes whenever folding changes the lines shown.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the line `index` counted from the oldest line shown.
+    pub(crate) fn line(&mut self, index: usize) -> Option<Vec<Cell>> {
+        match self.source(index)? {
+            Source::Stored(absolute) => self.stored_line(absolute),
+            Source::Spliced(cells) => Some(cells.to_vec()),
+        }
+    }
+
+    /// Finds where the line shown at `index` comes from.
+    fn source(&self, index: usize) -> Option<Source<'_>> {
+        let mut absolute = self.base_line + index as u64;
+        for splice in &self.splices {
+            if absolute < splice.start {
+                break;
+            }
+            let offset = (absolute - splice.start) as usize;
+            let shown = splice.shown();
+            if offset < shown {
+                return Some(match (&splice.summary, &splice.tail) {
+                    (Some(summary), _) if splice.folded => {
+                        Source::Spliced(summary)
+                    },
+                    (_, Some((replaced, rows)))
+                        if offset + replaced >= splice.len =>
+                    {
+                        Source::Spliced(&rows[offset + replaced - splice.len])
+                    },
+                    _ => Source::Stored(absolute),
+                });
+            }
+            absolute = absolute - shown as u64 + splice.len as u64;
+        }
+        (absolute < self.base_line + self.stored_len as u64)
+            .then_some(Source::Stored(absolute))
+    }
+
+    /// End of the run of stored lines shown one after the other from
+    /// `absolute`, which is shown as stored.
+    fn stored_run_end(&self, absolute: u64) -> u64 {
+        let mut end = self.base_line + self.stored_len as u64;
+        for splice in &self.splices {
+            if splice.start > absolute {
+                return end.min(splice.start);
+            }
+            // The output of an unfolded summary follows its stored lines.
+            if let (false, Some((replaced, _))) = (splice.folded, &splice.tail)
+            {
+                let tail = splice.start + (splice.len - replaced) as u64;
+                if tail > absolute {
+                    end = end.min(tail);
+                }
+            }
+        }
+        end
+    }
+
+    /// Calls `visit` with each line shown from `index` on, oldest first.
+    /// Stored lines are decoded a chunk at a time.
+    pub(crate) fn visit_lines(
+        &mut self,
+        mut index: usize,
+        mut visit: impl FnMut(usize, &[Cell]),
+    ) {
+        let len = self.len();
+        while index < len {
+            let absolute = match self.source(index) {
+                Some(Source::Stored(absolute)) => absolute,
+                Some(Source::Spliced(cells)) => {
+                    visit(index, cells);
+                    index += 1;
+                    continue;
+                },
+                None => return,
+            };
+            let end = self.stored_run_end(absolute);
+            let open_line = self.open_line();
+            if absolute >= open_line {
+                let open =
+                    (absolute - open_line) as usize..(end - open_line) as usize;
+                for line in &self.open_chunk[open] {
+                    visit(index, &decode_line(line));
+                    index += 1;
+                }
+                continue;
+            }
+
+            // A chunk that cannot be read is skipped a line at a time.
+            let Some((first_line, lines)) = self.chunk_lines(absolute) else {
+                index += 1;
+                continue;
+            };
+            let end = end.min(first_line + lines.len() as u64);
+            let run =
+                (absolute - first_line) as usize..(end - first_line) as usize;
+            for line in &lines[run] {
+                visit(index, line);
+                index += 1;
+            }
+        }
+    }
+
+    /// Index at which the lines of `splice` are shown.
+    fn splice_index(&self, splice: usize) -> usize {
+        let before = self.splices[..splice]
+            .iter()
+            .map(|splice| splice.shown() as isize - splice.len as isize)
+            .sum::<isize>();
+        ((self.splices[splice].start - self.base_line) as usize)
+            .saturating_add_signed(before)
+    }
+
+    /// Folds the `len` lines shown from `index` into `summary`, or hides
+    /// them when the summary is in the grid. Output of command `id` that
+    /// was unfolded before is folded as a whole. Returns the index the
+    /// lines were shown at and how many there were.
+    pub(crate) fn fold(
+        &mut self,
+        id: u64,
+        index: usize,
+        len: usize,
+        summary: Option<Vec<Cell>>,
+    ) -> Option<(usize, usize)> {
+        let position = match self.splices.iter().position(|s| s.id == id) {
+            Some(position) => position,
+            None => {
+                let start = self.stored_at(index)?;
+                let last = self.stored_at(index + len.checked_sub(1)?)?;
+                let position =
+                    self.splices.partition_point(|s| s.start < start);
+                self.splices.insert(
+                    position,
+                    Splice {
+                        id,
+                        start,
+                        len: (last - start) as usize + 1,
+                        summary: None,
+                        folded: false,
+                        tail: None,
+                    },
+                );
+                position
+            },
+        };
+
+        let index = self.splice_index(position);
+        let splice = &mut self.splices[position];
+        let shown = splice.shown();
+        splice.summary = summary;
+        splice.folded = true;
+        self.generation += 1;
+        Some((index, shown))
+    }
+
+    /// Shows the output of command `id` folded in the store again. Returns
+    /// the index it is shown at and how many lines were shown before.
+    pub(crate) fn unfold(&mut self, id: u64) -> Option<(usize, usize)> {
+        let position = self.splices.iter().position(|s| s.id == id)?;
+        let index = self.splice_index(position);
+        let splice = &mut self.splices[position];
+        let shown = splice.shown();
+        splice.folded = false;
+        if splice.tail.is_none() {
+            self.splices.remove(position);
+        }
+        self.generation += 1;
+        Some((index, shown))
+    }
+
+    /// Replaces the line shown at `index`, the summary of command `id`
+    /// that left the grid folded, and the `blanked` lines above it with the
+    /// `rows` it folded, together with the output hidden above them.
+    /// Returns the index the unfolded output is shown at.
+    pub(crate) fn unfold_summary(
+        &mut self,
+        id: u64,
+        index: usize,
+        blanked: usize,
+        rows: Vec<Vec<Cell>>,
+    ) -> Option<usize> {
+        let summary = self.stored_at(index)?;
+        let first = self.stored_at(index.checked_sub(blanked)?)?;
+        if summary - first != blanked as u64 {
+            return None;
+        }
+        let position = match self.splices.iter().position(|s| s.id == id) {
+            Some(position) => {
+                let splice = &mut self.splices[position];
+                if splice.start + splice.len as u64 != first {
+                    return None;
+                }
+                splice.len += blanked + 1;
+                position
+            },
+            None => {
+                let position =
+                    self.splices.partition_point(|s| s.start < first);
+                self.splices.insert(
+                    position,
+                    Splice {
+                        id,
+                        start: first,
+                        len: blanked + 1,
+                        summary: None,
+                        folded: false,
+                        tail: None,
+                    },
+                );
+                position
+            },
+        };
+
+        let splice = &mut self.splices[position];
+        splice.folded = false;
+        splice.tail = Some((blanked + 1, rows));
+        self.generation += 1;
+        Some(self.splice_index(position))
+    }
+
+    /// Absolute number of the stored line shown at `index`, `None` if a
+    /// splice shows something else there.
+    fn stored_at(&self, index: usize) -> Option<u64> {
+        match self.source(index)? {
+            Source::Stored(absolute) => Some(absolute),
+            Source::Spliced(_) => None,
+        }
+    }
+
+    /// Returns the stored line numbered `absolute`.
+    fn stored_line(&mut self, absolute: u64) -> Option<Vec<Cell>> {
+        let open_line = self.open_line();
+        if absolute >= open_line {
+            let open_index = (absolute - open_line) as usize;
+            return self.open_chunk.get(open_index).map(|l| decode_line(l));
+        }
+
+        let (first_line, lines) = self.chunk_lines(absolute)?;
+        lines.get((absolute - first_line) as usize).cloned()
+    }
+
+    /// Returns the first line and the decoded lines of the sealed chunk
+    /// that holds `absolute`.
+    fn chunk_lines(&mut self, absolute: u64) -> Option<(u64, &[Vec<Cell>])> {
+        let (segment_index, chunk_index) = self.locate(absolute)?;
+        let first_line =
+            self.segments[segment_index].chunks[chunk_index].first_line;
+        let cached = self
+            .cache
+            .iter()
+            .position(|(first, _)| *first == first_line);
+        let position = match cached {
+            Some(position) => position,
+            None => {
+                let lines =
+                    self.decode_chunk(segment_index, chunk_index).ok()?;
+                if self.cache.len() >= CACHED_CHUNKS {
+                    self.cache.pop_front();
+                }
+                self.cache.push_back((first_line, lines));
+                self.cache.len() - 1
+            },
+        };
+        Some((first_line, &self.cache[position].1))
+    }
+
+    /// Finds the segment and sealed chunk that hold `absolute`.
+    fn locate(&self, absolute: u64) -> Option<(usize, usize)> {
+        if absolute < self.base_line || absolute >= self.open_line() {
+            return None;
+        }
+
+        let before =
+            |chunk: &Chunk| chunk.first_line + chunk.lines as u64 <= absolute;
+        let segment_index = self.segments.partition_point(|segment| {
+            segment.chunks.last().is_some_and(before)
+        });
+        let chunks = &self.segments.get(segment_index)?.chunks;
+        let chunk_index = chunks.partition_point(before);
+        (chunk_index < chunks.len()).then_some((segment_index, chunk_index))
+    }
+
+    fn decode_chunk(
+        &mut self,
+        segment_index: usize,
+        chunk_index: usize,
+    ) -> io::Result<Vec<Vec<Cell>>> {
+        let decoded = self.segments[segment_index].read_chunk(chunk_index)?;
+        let mut lines = Vec::new();
+        let mut input = decoded.as_slice();
+        while !input.is_empty() {
+            let len =
+                read_varint(&mut input).ok_or_else(invalid_data)? as usize;
+            if input.len() < len {
+                return Err(invalid_data());
+            }
+            lines.push(decode_line(&input[..len]));
+            input = &input[len..];
+        }
+        Ok(lines)
+    }
+
+    /// Returns the indices of all stored lines matching `regex`, oldest
+    /// first.
+    pub(crate) fn search(&mut self, regex: &Regex) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.visit_lines(0, |index, cells| {
+            if regex.is_match(&line_text(cells)) {
+                found.push(index);
+            }
+        });
+        found
+    }
+}
+
+/// Matches of a search in an [`OverflowStore`], updated as lines are
+/// spilled into it and evicted from it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OverflowMatches {
+    query: String,
+    regex: Option<Regex>,
+    /// Store generation the matches were found in.
+    generation: u64,
+    /// Absolute line the next update starts searching at.
+    searched: u64,
+    /// Absolute line, first and last column of each match.
+    matches: Vec<(u64, Column, Column)>,
+}
+
+impl OverflowMatches {
+    /// Searches the lines stored since the last update for `query` and
+    /// returns all matches, oldest first, at the lines above the grid's
+    /// `topmost` line that the stored lines are shown at.
+    pub(crate) fn update(
+        &mut self,
+        query: &str,
+        store: &mut OverflowStore,
+        topmost: Line,
+    ) -> Vec<Match> {
+        if self.query != query || self.generation != store.generation() {
+            *self = Self {
+                query: query.to_string(),
+                regex: Regex::new(query).ok(),
+                generation: store.generation(),
+                ..Self::default()
+            };
+        }
+
+        let first = store.first_line();
+        let end = first + store.len() as u64;
+        self.matches.retain(|(line, ..)| *line >= first);
+        if let Some(regex) = &self.regex {
+            let from = (self.searched.max(first) - first) as usize;
+            let matches = &mut self.matches;
+            store.visit_lines(from, |index, cells| {
+                let line = first + index as u64;
+                matches.extend(
+                    line_matches(regex, cells)
+                        .into_iter()
+                        .map(|(start, end)| (line, start, end)),
+                );
+            });
+        }
+        self.searched = end;
+
+        self.matches
+            .iter()
+            .map(|&(line, start, last)| {
+                let line = Line(topmost.0 - (end - line) as i32);
+                Point::new(line, start)..=Point::new(line, last)
+            })
+            .collect()
+    }
+}
+
+/// First and last column of each match of `regex` in a stored line.
+fn line_matches(regex: &Regex, cells: &[Cell]) -> Vec<(Column, Column)> {
+    let mut text = String::new();
+    let mut columns = Vec::new();
+    for (column, cell) in cells.iter().enumerate() {
+        if !cell.flags.contains(Flags::WIDE_CHAR_SPACER) {
+            columns.push((text.len(), column));
+            push_cell_text(&mut text, cell);
+        }
+    }
+
+    let column_at = |offset: usize| {
+        columns[columns.partition_point(|&(start, _)| start <= offset) - 1].1
+    };
+    regex
+        .find_iter(&text)
+        .filter(|found| !found.is_empty())
+        .map(|found| {
+            let last = column_at(found.end() - 1);
+            let last = match cells[last].flags.contains(Flags::WIDE_CHAR) {
+                true => last + 1,
+                false => last,
+            };
+            (Column(column_at(found.start())), Column(last))
+        })
+        .collect()
+}
+
+pub(crate) fn line_text(cells: &[Cell]) -> String {
+    let mut text = String::new();
+    for cell in cells {
+        if !cell.flags.contains(Flags::WIDE_CHAR_SPACER) {
+            push_cell_text(&mut text, cell);
+        }
+    }
+    text
+}
+
+fn push_cell_text(text: &mut String, cell: &Cell) {
+    text.push(cell.c);
+    text.extend(cell.zerowidth().into_iter().flatten());
+}
+
+fn is_blank(cell: &Cell) -> bool {
+    cell.c == ' '
+        && cell.bg == Color::Named(NamedColor::Background)
+        && cell.flags.is_empty()
+        && cell.extra.is_none()
+}
+
+fn encode_cell(out: &mut Vec<u8>, cell: &Cell) {
+    write_varint(out, cell.c as u64);
+    out.extend_from_slice(&cell.flags.bits().to_le_bytes());
+    encode_color(out, cell.fg);
+    encode_color(out, cell.bg);
+
+    let zerowidth = cell.zerowidth().filter(|chars| !chars.is_empty());
+    let underline_color = cell.underline_color();
+    let hyperlink = cell.hyperlink();
+    let mut parts = 0;
+    if zerowidth.is_some() {
+        parts |= HAS_ZEROWIDTH;
+    }
+    if underline_color.is_some() {
+        parts |= HAS_UNDERLINE_COLOR;
+    }
+    if hyperlink.is_some() {
+        parts |= HAS_HYPERLINK;
+    }
+    out.push(parts);
+
+    if let Some(chars) = zerowidth {
+        write_varint(out, chars.len() as u64);
+        for &c in chars {
+            write_varint(out, c as u64);
+        }
+    }
+    if let Some(color) = underline_color {
+        encode_color(out, color);
+    }
+    if let Some(hyperlink) = hyperlink {
+        encode_str(out, hyperlink.id());
+        encode_str(out, hyperlink.uri());
+    }
+}
+
+fn encode_str(out: &mut Vec<u8>, text: &str) {
+    write_varint(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn encode_color(out: &mut Vec<u8>, color: Color) {
+    match color {
+        Color::Named(named) => {
+            let index = NAMED_COLORS.iter().position(|c| *c == named);
+            out.extend_from_slice(&[0, index.unwrap_or(16) as u8]);
+        },
+        Color::Spec(rgb) => out.extend_from_slice(&[1, rgb.r, rgb.g, rgb.b]),
+        Color::Indexed(index) => out.extend_from_slice(&[2, index]),
+    }
+}
+
+fn decode_line(mut input: &[u8]) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    let count = read_varint(&mut input).unwrap_or(0);
+    for _ in 0..count {
+        match decode_cell(&mut input) {
+            Some(cell) => cells.push(cell),
+            None => break,
+        }
+    }
+    cells
+}
+
+fn decode_cell(input: &mut &[u8]) -> Option<Cell> {
+    let c = char::from_u32(read_varint(input)? as u32)?;
+    let flags = u16::from_le_bytes([take(input)?, take(input)?]);
+    let fg = decode_color(input)?;
+    let bg = decode_color(input)?;
+    let mut cell = Cell {
+        c,
+        fg,
+        bg,
+        flags: Flags::from_bits_truncate(flags),
+        extra: None,
+    };
+
+    let parts = take(input)?;
+    if parts & HAS_ZEROWIDTH != 0 {
+        for _ in 0..read_varint(input)? {
+            cell.push_zerowidth(char::from_u32(read_varint(input)? as u32)?);
+        }
+    }
+    if parts & HAS_UNDERLINE_COLOR != 0 {
+        cell.set_underline_color(Some(decode_color(input)?));
+    }
+    if parts & HAS_HYPERLINK != 0 {
+        let id = decode_str(input)?;
+        let uri = decode_str(input)?;
+        cell.set_hyperlink(Some(Hyperlink::new(Some(id), uri)));
+    }
+    Some(cell)
+}
+
+fn decode_str(input: &mut &[u8]) -> Option<String> {
+    let len = read_varint(input)? as usize;
+    if input.len() < len {
+        return None;
+    }
+    let (text, rest) = input.split_at(len);
+    *input = rest;
+    String::from_utf8(text.to_vec()).ok()
+}
+
+fn decode_color(input: &mut &[u8]) -> Option<Color> {
+    match take(input)? {
+        0 => NAMED_COLORS
+            .get(take(input)? as usize)
+            .copied()
+            .map(Color::Named),
+        1 => Some(Color::Spec(Rgb {
+            r: take(input)?,
+            g: take(input)?,
+            b: take(input)?,
+        })),
+        2 => Some(Color::Indexed(take(input)?)),
+        _ => None,
+    }
+}
+
+fn take(input: &mut &[u8]) -> Option<u8> {
+    let (first, rest) = input.split_first()?;
+    *input = rest;
+    Some(*first)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupted overflow chunk")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{line_text, OverflowStore};
+    use crate::backend::settings::{OverflowSettings, OverflowStorage};
+    use alacritty_terminal::event::VoidListener;
+    use alacritty_terminal::grid::Dimensions;
+    use alacritty_terminal::term::test::TermSize;
+    use alacritty_terminal::term::{Config, Term};
+    use alacritty_terminal::vte::ansi::Processor;
+    use regex::Regex;
+
+    fn filled_term(store: &OverflowStore, lines: usize) -> Term<VoidListener> {
+        let config = Config {
+            scrolling_history: store.grid_history(),
+            ..Config::default()
+        };
+        let mut term = Term::new(config, &TermSize::new(20, 5), VoidListener);
+        let mut parser: Processor = Processor::new();
+        for line in 0..lines {
+            parser.advance(&mut term, format!("line {line}\r\n").as_bytes());
+        }
+        term
+    }
+
+    fn spill_lines(storage: OverflowStorage) {
+        let settings = OverflowSettings {
+            storage,
+            budget_bytes: usize::MAX,
+            spill_margin: 1000,
+        };
+        let mut store = OverflowStore::new(settings, 10);
+        let mut term = filled_term(&store, 700);
+        store.spill(&mut term);
+
+        // 700 lines plus the empty cursor line, 5 on screen, 10 kept.
+        assert_eq!(term.grid().history_size(), 10);
+        assert_eq!(store.len(), 686);
+        assert_eq!(line_text(&store.line(0).unwrap()), "line 0");
+        assert_eq!(line_text(&store.line(685).unwrap()), "line 685");
+        assert_eq!(line_text(&store.line(300).unwrap()), "line 300");
+        assert!(store.line(686).is_none());
+
+        let matches = store.search(&Regex::new(r"^line 6\d\d$").unwrap());
+        assert_eq!(matches.len(), 86);
+        assert_eq!(matches[0], 600);
+    }
+
+    #[test]
+    fn visiting_lines_reads_them_as_shown() {
+        let settings = OverflowSettings {
+            storage: OverflowStorage::Memory,
+            budget_bytes: usize::MAX,
+            spill_margin: 1000,
+        };
+        let mut store = OverflowStore::new(settings, 10);
+        let mut term = filled_term(&store, 700);
+        store.spill(&mut term);
+
+        // Folds across a chunk boundary, hidden output and the output of
+        // a spilled summary.
+        let summary = store.line(0).unwrap();
+        store.fold(1, 250, 10, Some(summary.clone())).unwrap();
+        store.fold(2, 400, 5, None).unwrap();
+        store.unfold_summary(3, 600, 2, vec![summary; 4]).unwrap();
+
+        let read = |store: &mut OverflowStore, from: usize| {
+            (from..store.len())
+                .map(|index| (index, line_text(&store.line(index).unwrap())))
+                .collect::<Vec<_>>()
+        };
+        for from in [0, 255, 300, 599, 680] {
+            let mut visited = Vec::new();
+            store.visit_lines(from, |index, cells| {
+                visited.push((index, line_text(cells)));
+            });
+            assert_eq!(visited, read(&mut store, from));
+        }
+    }
+
+    #[test]
+    fn spilled_lines_keep_combining_characters_links_and_underline_colors() {
+        use alacritty_terminal::index::Line;
+        use alacritty_terminal::term::cell::Cell;
+
+        let settings = OverflowSettings {
+            storage: OverflowStorage::Memory,
+            budget_bytes: usize::MAX,
+            spill_margin: 1000,
+        };
+        let mut store = OverflowStore::new(settings, 0);
+        let mut term = filled_term(&store, 0);
+        let mut parser: Processor = Processor::new();
+        parser.advance(
+            &mut term,
+            "cafe\u{301} \x1b]8;id=doc;https://example.com\x1b\\link\x1b]8;;\x1b\\ \
+             \x1b[4;58:2::255:0:0mred\x1b[0m\r\n"
+                .as_bytes(),
+        );
+        let row: Vec<Cell> =
+            term.grid()[Line(0)].into_iter().cloned().collect();
+        for line in 0..10 {
+            parser.advance(&mut term, format!("line {line}\r\n").as_bytes());
+        }
+        store.spill(&mut term);
+
+        let cells = store.line(0).unwrap();
+        assert_eq!(cells[..], row[..cells.len()]);
+        assert_eq!(line_text(&cells), "cafe\u{301} link red");
+        assert_eq!(cells[3].zerowidth(), Some(&['\u{301}'][..]));
+        let link = cells[5].hyperlink().unwrap();
+        assert_eq!((link.id(), link.uri()), ("doc", "https://example.com"));
+        assert!(cells[10].underline_color().is_some());
+        let matches = store.search(&Regex::new("cafe\u{301}").unwrap());
+        assert_eq!(matches, [0]);
+    }
+
+    #[test]
+    fn spilled_lines_are_readable_from_memory() {
+        spill_lines(OverflowStorage::Memory);
+    }
+
+    #[test]
+    fn spilled_lines_are_readable_from_disk() {
+        let path = std::env::temp_dir()
+            .join(format!("egui_term_overflow_{}", std::process::id()));
+        spill_lines(OverflowStorage::File(path.clone()));
+        assert!(!path.with_extension("0").exists());
+    }
+
+    #[test]
+    fn lines_stay_open_until_they_can_be_stored() {
+        let dir = std::env::temp_dir()
+            .join(format!("egui_term_missing_{}", std::process::id()));
+        let settings = OverflowSettings {
+            storage: OverflowStorage::File(dir.join("overflow")),
+            budget_bytes: usize::MAX,
+            spill_margin: 1000,
+        };
+        let mut store = OverflowStore::new(settings, 10);
+        let mut term = filled_term(&store, 700);
+        store.spill(&mut term);
+        assert_eq!(store.len(), 686);
+        assert_eq!(line_text(&store.line(0).unwrap()), "line 0");
+
+        std::fs::create_dir(&dir).unwrap();
+        let mut parser: Processor = Processor::new();
+        for line in 700..1000 {
+            parser.advance(&mut term, format!("line {line}\r\n").as_bytes());
+        }
+        store.spill(&mut term);
+        assert!(dir.join("overflow.0").exists());
+        assert_eq!(store.len(), 986);
+        assert_eq!(line_text(&store.line(0).unwrap()), "line 0");
+        assert_eq!(line_text(&store.line(985).unwrap()), "line 985");
+        drop(store);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    /// Backend keeping 50 lines of history, with `lines` lines printed.
+    fn overflowing_backend(
+        lines: usize,
+    ) -> (crate::TerminalBackend, crate::ChannelPeer) {
+        use crate::backend::settings::{BackendSettings, TerminalConfig};
+        use crate::{ChannelSource, TerminalBackend};
+        use std::time::Duration;
+
+        let (source, peer) = ChannelSource::new();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut backend = TerminalBackend::with_source(
+            0,
+            egui::Context::default(),
+            sender,
+            Box::new(source),
+            BackendSettings {
+                terminal_config: TerminalConfig {
+                    scrolling_history: 50,
+                    ..TerminalConfig::default()
+                },
+                overflow: Some(OverflowSettings::default()),
+                ..BackendSettings::default()
+            },
+        )
+        .unwrap();
+        let text: String =
+            (0..lines).map(|line| format!("line {line}\r\n")).collect();
+        peer.output.send(text.into_bytes()).unwrap();
+        let last = format!("line {}", lines - 1);
+        let timeout = Duration::from_secs(5);
+        backend.wait_for_regex(&last, timeout).unwrap();
+        backend.sync();
+        (backend, peer)
+    }
+
+    #[test]
+    fn selection_skips_the_overflow_rows() {
+        use crate::backend::SelectionType;
+        use crate::{BackendCommand, TerminalBackend};
+
+        let (mut backend, _peer) = overflowing_backend(300);
+        let stored = backend.overflow_line_count();
+        let row = |row: f32| row + 0.5;
+        let select = |backend: &mut TerminalBackend, start: f32, end: f32| {
+            backend.process_command(BackendCommand::SelectStart(
+                SelectionType::Simple,
+                0.0,
+                row(start),
+            ));
+            backend
+                .process_command(BackendCommand::SelectUpdate(79.5, row(end)));
+            backend.sync();
+            backend.selectable_content()
+        };
+
+        // Two overflow rows on top of the oldest grid line.
+        backend.scroll_to_overflow(stored - 2);
+        let text = select(&mut backend, 2.0, 3.0);
+        let lines: Vec<_> = text.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            [format!("line {stored}"), format!("line {}", stored + 1)]
+        );
+        assert_eq!(select(&mut backend, 1.0, 3.0), "");
+        // Dragging up into them stops at the grid.
+        let text = select(&mut backend, 3.0, 0.0);
+        assert_eq!(
+            text.lines().next().unwrap().trim_end(),
+            format!("line {stored}")
+        );
+    }
+
+    #[test]
+    fn search_finds_and_scrolls_to_stored_lines() {
+        use alacritty_terminal::index::Point;
+
+        let (mut backend, peer) = overflowing_backend(300);
+        backend.search_set_active(true);
+        backend.search_set_query("line [12]50");
+        let matches = backend.last_content().search_state.matches.clone();
+        assert_eq!(matches.len(), 2);
+
+        let row_text = |backend: &mut crate::TerminalBackend, point: Point| {
+            backend
+                .sync()
+                .cells
+                .iter()
+                .filter(|indexed| indexed.point.line == point.line)
+                .map(|indexed| indexed.cell.c)
+                .collect::<String>()
+        };
+        for (found, text) in matches.iter().zip(["line 150", "line 250"]) {
+            backend.scroll_to_point(*found.start());
+            assert_eq!(row_text(&mut backend, *found.start()).trim_end(), text);
+            let search = &backend.last_content().search_state;
+            assert!(search.point_in_match(*found.end()).is_some());
+        }
+        assert!(backend.last_content().display_offset > 0);
+
+        // More output moves the stored matches along with their rows.
+        let text: String =
+            (300..400).map(|line| format!("line {line}\r\n")).collect();
+        peer.output.send(text.into_bytes()).unwrap();
+        let timeout = std::time::Duration::from_secs(5);
+        backend.scroll_to_bottom();
+        backend.wait_for_regex("line 399", timeout).unwrap();
+        backend.search_set_query("line [12]50");
+        let matches = backend.last_content().search_state.matches.clone();
+        assert_eq!(matches.len(), 2);
+        backend.scroll_to_point(*matches[1].start());
+        let text = row_text(&mut backend, *matches[1].start());
+        assert_eq!(text.trim_end(), "line 250");
+    }
+
+    #[test]
+    fn lowering_the_history_spills_into_the_store() {
+        use crate::backend::settings::{BackendSettings, TerminalConfig};
+        use crate::{BackendCommand, ChannelSource, TerminalBackend};
+        use std::time::Duration;
+
+        let (source, peer) = ChannelSource::new();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut backend = TerminalBackend::with_source(
+            0,
+            egui::Context::default(),
+            sender,
+            Box::new(source),
+            BackendSettings {
+                overflow: Some(OverflowSettings {
+                    spill_margin: 100,
+                    ..OverflowSettings::default()
+                }),
+                ..BackendSettings::default()
+            },
+        )
+        .unwrap();
+        let text: String =
+            (0..600).map(|line| format!("line {line}\r\n")).collect();
+        peer.output.send(text.into_bytes()).unwrap();
+        let timeout = Duration::from_secs(5);
+        backend.wait_for_regex("line 599", timeout).unwrap();
+
+        let screen_lines = backend.sync().terminal_size.num_lines as usize;
+        backend.process_command(BackendCommand::UpdateConfig(TerminalConfig {
+            scrolling_history: 50,
+            ..TerminalConfig::default()
+        }));
+        let mut store = backend.overflow.as_ref().unwrap().lock().unwrap();
+        assert_eq!(store.len(), 601 - screen_lines - 50);
+        assert_eq!(line_text(&store.line(0).unwrap()), "line 0");
+    }
+
+    #[test]
+    fn oldest_lines_are_evicted_over_budget() {
+        let settings = OverflowSettings {
+            storage: OverflowStorage::Memory,
+            budget_bytes: 1,
+            spill_margin: 5000,
+        };
+        let mut store = OverflowStore::new(settings, 0);
+        let mut term = filled_term(&store, 3000);
+        store.spill(&mut term);
+
+        assert!(store.len() < 3000);
+        let last = store.len() - 1;
+        assert_eq!(line_text(&store.line(last).unwrap()), "line 2995");
+    }
+}
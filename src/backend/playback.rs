@@ -0,0 +1,660 @@
+//! Replays asciicast v2 recordings and raw byte logs into a `Term`.
+
+use std::io::{self, Result};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use alacritty_terminal::event::{Event, EventListener};
+use alacritty_terminal::sync::FairMutex;
+use alacritty_terminal::term::{self, test::TermSize, Term};
+use alacritty_terminal::vte::ansi::Processor;
+
+use super::EventProxy;
+
+#[derive(Debug, Clone, PartialEq)]
+enum RecordedEvent {
+    Output(Vec<u8>),
+    Resize(usize, usize),
+}
+
+/// A session loaded for playback with
+/// [`crate::TerminalBackend::new_playback`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    pub columns: usize,
+    pub lines: usize,
+    events: Vec<(Duration, RecordedEvent)>,
+}
+
+impl Recording {
+    /// Parses an asciicast v2 file. Input events are skipped.
+    pub fn from_asciicast(cast: &str) -> Result<Self> {
+        let mut lines = cast.lines().filter(|line| !line.trim().is_empty());
+        let header = lines.next().ok_or_else(|| invalid("empty recording"))?;
+        let header = json::parse(header)?;
+        if header.get("version").and_then(json::Value::as_f64) != Some(2.0) {
+            return Err(invalid("only asciicast v2 is supported"));
+        }
+        let size = |key| {
+            header
+                .get(key)
+                .and_then(json::Value::as_f64)
+                .map(|value| value as usize)
+                .ok_or_else(|| invalid(&format!("header has no {key}")))
+        };
+
+        let mut recording = Self {
+            columns: size("width")?,
+            lines: size("height")?,
+            events: Vec::new(),
+        };
+        for line in lines {
+            let json::Value::Array(event) = json::parse(line)? else {
+                return Err(invalid("event is not an array"));
+            };
+            let (Some(time), Some(code), Some(data)) = (
+                event.first().and_then(json::Value::as_f64),
+                event.get(1).and_then(json::Value::as_str),
+                event.get(2).and_then(json::Value::as_str),
+            ) else {
+                return Err(invalid("malformed event"));
+            };
+
+            let time = Duration::from_secs_f64(time.max(0.0));
+            match code {
+                "o" => recording.events.push((
+                    time,
+                    RecordedEvent::Output(data.as_bytes().to_vec()),
+                )),
+                "r" => {
+                    let size =
+                        data.split_once('x').and_then(|(cols, lines)| {
+                            Some((cols.parse().ok()?, lines.parse().ok()?))
+                        });
+                    if let Some((columns, lines)) = size {
+                        recording.events.push((
+                            time,
+                            RecordedEvent::Resize(columns, lines),
+                        ));
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        Ok(recording)
+    }
+
+    pub fn load_asciicast(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_asciicast(&std::fs::read_to_string(path)?)
+    }
+
+    /// Wraps a raw byte log without timing information. Every line becomes
+    /// one event, `line_interval` apart.
+    pub fn from_raw_log(
+        log: &[u8],
+        columns: usize,
+        lines: usize,
+        line_interval: Duration,
+    ) -> Self {
+        let events = log
+            .split_inclusive(|byte| *byte == b'\n')
+            .enumerate()
+            .map(|(index, chunk)| {
+                (
+                    line_interval * index as u32,
+                    RecordedEvent::Output(chunk.to_vec()),
+                )
+            })
+            .collect();
+
+        Self {
+            columns,
+            lines,
+            events,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.events.last().map_or(Duration::ZERO, |(time, _)| *time)
+    }
+
+    pub fn event_count(&self) -> usize {
+        self.events.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackCommand {
+    Play,
+    Pause,
+    /// Playback speed multiplier, `1.0` is real time.
+    SetSpeed(f32),
+    /// Jumps to a position. Seeking forward continues from the current
+    /// state, seeking backward re-parses the recording from the start.
+    Seek(Duration),
+    /// Pauses and applies the next recorded event.
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackStatus {
+    pub position: Duration,
+    pub duration: Duration,
+    pub playing: bool,
+    pub speed: f32,
+    /// Index of the next event to apply.
+    pub next_event: usize,
+}
+
+pub(crate) struct Player {
+    recording: Recording,
+    term: Arc<FairMutex<Term<EventProxy>>>,
+    config: term::Config,
+    event_proxy: EventProxy,
+    parser: Processor,
+    status: Arc<Mutex<PlaybackStatus>>,
+    /// Wall clock instant matching `status.position` while playing.
+    clock: Instant,
+}
+
+impl Player {
+    pub(crate) fn new(
+        recording: Recording,
+        term: Arc<FairMutex<Term<EventProxy>>>,
+        config: term::Config,
+        event_proxy: EventProxy,
+    ) -> (Self, Arc<Mutex<PlaybackStatus>>) {
+        let status = Arc::new(Mutex::new(PlaybackStatus {
+            position: Duration::ZERO,
+            duration: recording.duration(),
+            playing: false,
+            speed: 1.0,
+            next_event: 0,
+        }));
+        let player = Self {
+            recording,
+            term,
+            config,
+            event_proxy,
+            parser: Processor::new(),
+            status: status.clone(),
+            clock: Instant::now(),
+        };
+
+        (player, status)
+    }
+
+    pub(crate) fn run(mut self, commands: Receiver<PlaybackCommand>) {
+        loop {
+            let status = *self.status.lock().unwrap();
+            let next = self.recording.events.get(status.next_event);
+            let timeout = match next {
+                Some((time, _)) if status.playing => {
+                    let elapsed = self.clock.elapsed().mul_f32(status.speed);
+                    let position = status.position + elapsed;
+                    time.saturating_sub(position).div_f32(status.speed)
+                },
+                // Nothing scheduled, wait for the next command.
+                _ => Duration::from_secs(3600),
+            };
+
+            match commands.recv_timeout(timeout) {
+                Ok(command) => self.handle(command),
+                Err(RecvTimeoutError::Timeout) if status.playing => {
+                    if let Some((time, _)) = next {
+                        let time = *time;
+                        self.apply_until(time);
+                        self.update(|status| status.position = time);
+                        self.clock = Instant::now();
+                    }
+                    if self.at_end() {
+                        self.update(|status| status.playing = false);
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        // Ends the event subscription of the backend.
+        self.term.lock().exit();
+    }
+
+    fn handle(&mut self, command: PlaybackCommand) {
+        // Fold the time spent playing into the position before changing
+        // anything.
+        let now = Instant::now();
+        let elapsed = now - self.clock;
+        self.clock = now;
+        self.update(|status| {
+            if status.playing {
+                status.position = (status.position
+                    + elapsed.mul_f32(status.speed))
+                .min(status.duration);
+            }
+        });
+
+        match command {
+            PlaybackCommand::Play => {
+                if self.at_end() {
+                    self.seek(Duration::ZERO);
+                }
+                self.update(|status| status.playing = true);
+            },
+            PlaybackCommand::Pause => {
+                self.update(|status| status.playing = false);
+            },
+            PlaybackCommand::SetSpeed(speed) if speed > 0.0 => {
+                self.update(|status| status.speed = speed);
+            },
+            PlaybackCommand::SetSpeed(_) => {},
+            PlaybackCommand::Seek(position) => self.seek(position),
+            PlaybackCommand::Step => {
+                self.update(|status| status.playing = false);
+                let next = self.status.lock().unwrap().next_event;
+                if let Some((time, _)) = self.recording.events.get(next) {
+                    let time = *time;
+                    self.apply_events(next + 1);
+                    self.update(|status| status.position = time);
+                }
+            },
+        }
+    }
+
+    fn seek(&mut self, position: Duration) {
+        let position = position.min(self.recording.duration());
+        let current = self.status.lock().unwrap().position;
+        if position < current {
+            self.reset();
+        }
+        self.apply_until(position);
+        self.update(|status| status.position = position);
+    }
+
+    /// Starts over with an empty terminal of the recorded size.
+    fn reset(&mut self) {
+        let size = TermSize::new(self.recording.columns, self.recording.lines);
+        *self.term.lock() =
+            Term::new(self.config.clone(), &size, self.event_proxy.clone());
+        self.parser = Processor::new();
+        self.update(|status| {
+            status.position = Duration::ZERO;
+            status.next_event = 0;
+        });
+    }
+
+    fn apply_until(&mut self, position: Duration) {
+        let next = self.status.lock().unwrap().next_event;
+        let end = next
+            + self.recording.events[next..]
+                .iter()
+                .take_while(|(time, _)| *time <= position)
+                .count();
+        self.apply_events(end);
+    }
+
+    fn apply_events(&mut self, end: usize) {
+        let start = self.status.lock().unwrap().next_event;
+        if start >= end {
+            return;
+        }
+
+        let mut term = self.term.lock();
+        for (_, event) in &self.recording.events[start..end] {
+            match event {
+                RecordedEvent::Output(bytes) => {
+                    self.parser.advance(&mut *term, bytes)
+                },
+                RecordedEvent::Resize(columns, lines) => {
+                    term.resize(TermSize::new(*columns, *lines))
+                },
+            }
+        }
+        drop(term);
+
+        self.update(|status| status.next_event = end);
+        self.event_proxy.send_event(Event::Wakeup);
+    }
+
+    fn at_end(&self) -> bool {
+        self.status.lock().unwrap().next_event >= self.recording.events.len()
+    }
+
+    fn update(&self, update: impl FnOnce(&mut PlaybackStatus)) {
+        update(&mut self.status.lock().unwrap());
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Just enough JSON to read asciicast files.
+mod json {
+    use std::io::Result;
+
+    use super::invalid;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub(super) enum Value {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        pub(super) fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Value::Object(fields) => fields
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        pub(super) fn as_f64(&self) -> Option<f64> {
+            match self {
+                Value::Number(number) => Some(*number),
+                _ => None,
+            }
+        }
+
+        pub(super) fn as_str(&self) -> Option<&str> {
+            match self {
+                Value::String(string) => Some(string),
+                _ => None,
+            }
+        }
+    }
+
+    pub(super) fn parse(input: &str) -> Result<Value> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos != parser.input.len() {
+            return Err(invalid("trailing characters after JSON value"));
+        }
+        Ok(value)
+    }
+
+    struct Parser<'a> {
+        input: &'a [u8],
+        pos: usize,
+    }
+
+    impl Parser<'_> {
+        fn whitespace(&mut self) {
+            while self
+                .input
+                .get(self.pos)
+                .is_some_and(|byte| byte.is_ascii_whitespace())
+            {
+                self.pos += 1;
+            }
+        }
+
+        fn peek(&mut self) -> Option<u8> {
+            self.whitespace();
+            self.input.get(self.pos).copied()
+        }
+
+        fn expect(&mut self, byte: u8) -> Result<()> {
+            if self.peek() == Some(byte) {
+                self.pos += 1;
+                Ok(())
+            } else {
+                Err(invalid(&format!("expected '{}'", byte as char)))
+            }
+        }
+
+        fn literal(&mut self, literal: &str, value: Value) -> Result<Value> {
+            if self.input[self.pos..].starts_with(literal.as_bytes()) {
+                self.pos += literal.len();
+                Ok(value)
+            } else {
+                Err(invalid("invalid literal"))
+            }
+        }
+
+        fn value(&mut self) -> Result<Value> {
+            match self.peek().ok_or_else(|| invalid("unexpected end"))? {
+                b'{' => self.object(),
+                b'[' => self.array(),
+                b'"' => self.string().map(Value::String),
+                b't' => self.literal("true", Value::Bool(true)),
+                b'f' => self.literal("false", Value::Bool(false)),
+                b'n' => self.literal("null", Value::Null),
+                _ => self.number(),
+            }
+        }
+
+        fn object(&mut self) -> Result<Value> {
+            self.expect(b'{')?;
+            let mut fields = Vec::new();
+            if self.peek() == Some(b'}') {
+                self.pos += 1;
+                return Ok(Value::Object(fields));
+            }
+            loop {
+                self.whitespace();
+                let key = self.string()?;
+                self.expect(b':')?;
+                fields.push((key, self.value()?));
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    _ => break,
+                }
+            }
+            self.expect(b'}')?;
+            Ok(Value::Object(fields))
+        }
+
+        fn array(&mut self) -> Result<Value> {
+            self.expect(b'[')?;
+            let mut items = Vec::new();
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(Value::Array(items));
+            }
+            loop {
+                items.push(self.value()?);
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    _ => break,
+                }
+            }
+            self.expect(b']')?;
+            Ok(Value::Array(items))
+        }
+
+        fn number(&mut self) -> Result<Value> {
+            let start = self.pos;
+            while self.input.get(self.pos).is_some_and(|byte| {
+                byte.is_ascii_digit() || b"+-.eE".contains(byte)
+            }) {
+                self.pos += 1;
+            }
+            std::str::from_utf8(&self.input[start..self.pos])
+                .ok()
+                .and_then(|number| number.parse().ok())
+                .map(Value::Number)
+                .ok_or_else(|| invalid("invalid number"))
+        }
+
+        fn string(&mut self) -> Result<String> {
+            self.expect(b'"')?;
+            let mut bytes = Vec::new();
+            loop {
+                let byte = *self
+                    .input
+                    .get(self.pos)
+                    .ok_or_else(|| invalid("unterminated string"))?;
+                self.pos += 1;
+                match byte {
+                    b'"' => break,
+                    b'\\' => {
+                        let escape = *self
+                            .input
+                            .get(self.pos)
+                            .ok_or_else(|| invalid("unterminated string"))?;
+                        self.pos += 1;
+                        let c = match escape {
+                            b'"' => '"',
+                            b'\\' => '\\',
+                            b'/' => '/',
+                            b'b' => '\u{8}',
+                            b'f' => '\u{c}',
+                            b'n' => '\n',
+                            b'r' => '\r',
+                            b't' => '\t',
+                            b'u' => self.unicode_escape()?,
+                            _ => return Err(invalid("invalid escape")),
+                        };
+                        let mut buf = [0; 4];
+                        bytes.extend_from_slice(
+                            c.encode_utf8(&mut buf).as_bytes(),
+                        );
+                    },
+                    byte => bytes.push(byte),
+                }
+            }
+            String::from_utf8(bytes).map_err(|_| invalid("invalid UTF-8"))
+        }
+
+        fn hex4(&mut self) -> Result<u32> {
+            let hex = self
+                .input
+                .get(self.pos..self.pos + 4)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid("invalid unicode escape"))?;
+            self.pos += 4;
+            Ok(hex)
+        }
+
+        fn unicode_escape(&mut self) -> Result<char> {
+            let high = self.hex4()?;
+            let code = if (0xd800..0xdc00).contains(&high)
+                && self.input[self.pos..].starts_with(b"\\u")
+            {
+                self.pos += 2;
+                let low = self.hex4()?;
+                0x10000
+                    + ((high - 0xd800) << 10)
+                    + (low.wrapping_sub(0xdc00) & 0x3ff)
+            } else {
+                high
+            };
+            Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json, Recording};
+    use std::time::Duration;
+
+    const CAST: &str = r#"{"version": 2, "width": 20, "height": 4, "env": {"TERM": "xterm"}}
+[0.5, "o", "hello\r\n"]
+[0.7, "i", "ignored"]
+[1.25, "o", "\u001b[1m😀"]
+[2.0, "r", "30x5"]
+"#;
+
+    #[test]
+    fn parses_asciicast_v2() {
+        let recording = Recording::from_asciicast(CAST).unwrap();
+        assert_eq!((recording.columns, recording.lines), (20, 4));
+        assert_eq!(recording.event_count(), 3);
+        assert_eq!(recording.duration(), Duration::from_secs(2));
+        assert_eq!(
+            recording.events[1].1,
+            super::RecordedEvent::Output("\x1b[1m\u{1f600}".into())
+        );
+        assert_eq!(recording.events[2].1, super::RecordedEvent::Resize(30, 5));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert!(Recording::from_asciicast("{\"version\": 1}").is_err());
+        assert!(json::parse("[1, \"a\"").is_err());
+    }
+
+    #[test]
+    fn playback_steps_and_seeks() {
+        use super::PlaybackCommand;
+        use crate::backend::export::{ExportFormat, ExportRange};
+        use crate::types::Size;
+        use crate::{BackendCommand, BackendSettings, TerminalBackend};
+        use std::time::Instant;
+
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut backend = TerminalBackend::new_playback(
+            0,
+            egui::Context::default(),
+            sender,
+            Recording::from_asciicast(CAST).unwrap(),
+            BackendSettings::default(),
+        )
+        .unwrap();
+        let view = Size::new(800.0, 600.0);
+        let font = Size::new(10.0, 20.0);
+        backend.process_command(BackendCommand::Resize(view, font));
+        let size = backend.sync().terminal_size;
+        assert_eq!(
+            (size.num_cols, size.num_lines, size.cell_width),
+            (20, 4, 10)
+        );
+
+        let timeout = Duration::from_secs(5);
+        let mut run = |command, next_event| {
+            backend.process_command(BackendCommand::Playback(command));
+            let deadline = Instant::now() + timeout;
+            while backend.playback_status().unwrap().next_event != next_event {
+                assert!(Instant::now() < deadline, "playback never moved");
+                std::thread::yield_now();
+            }
+            backend.export(ExportRange::Screen, &ExportFormat::PlainText)
+        };
+
+        assert_eq!(run(PlaybackCommand::Step, 1), "hello\n\n\n\n");
+        assert_eq!(
+            run(PlaybackCommand::Seek(Duration::from_secs(3)), 3),
+            "hello\n\u{1f600}\n\n\n\n"
+        );
+        assert_eq!(run(PlaybackCommand::Seek(Duration::ZERO), 0), "\n\n\n\n");
+
+        run(PlaybackCommand::SetSpeed(100.0), 0);
+        run(PlaybackCommand::Play, 3);
+        // Playback stops by itself at the end of the recording.
+        let deadline = Instant::now() + timeout;
+        while backend.playback_status().unwrap().playing {
+            assert!(Instant::now() < deadline, "playback never stopped");
+            std::thread::yield_now();
+        }
+        let size = backend.sync().terminal_size;
+        assert_eq!((size.num_cols, size.num_lines), (30, 5));
+    }
+
+    #[test]
+    fn raw_logs_are_split_into_lines() {
+        let recording = Recording::from_raw_log(
+            b"one\ntwo\nthree",
+            80,
+            24,
+            Duration::from_millis(100),
+        );
+        assert_eq!(recording.event_count(), 3);
+        assert_eq!(recording.duration(), Duration::from_millis(200));
+    }
+}
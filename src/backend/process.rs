@@ -0,0 +1,397 @@
+//! The programs running in a terminal, for tab titles, for asking
+//! before a tab with a running program is closed and for signalling them.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use super::TerminalBackend;
+
+/// A process as seen in `/proc`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Program name as started, e.g. `vim` or `bash` for a `-bash` login
+    /// shell.
+    pub name: String,
+    pub argv: Vec<String>,
+    pub cwd: Option<PathBuf>,
+}
+
+impl ProcessInfo {
+    /// Reads process `pid` from `/proc`. Only supported on Linux.
+    pub fn read(pid: u32) -> Option<Self> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+
+        let proc = PathBuf::from(format!("/proc/{pid}"));
+        let cmdline = std::fs::read(proc.join("cmdline")).ok()?;
+        let argv: Vec<String> = cmdline
+            .split(|&byte| byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        // `comm` is the name of the executable after symlinks and cut to
+        // 15 bytes, argv[0] is what the user typed.
+        let name = argv
+            .first()
+            .map(|arg0| arg0.trim_start_matches('-'))
+            .and_then(|arg0| file_name(Path::new(arg0)))
+            .or_else(|| {
+                let comm = std::fs::read_to_string(proc.join("comm")).ok()?;
+                Some(comm.trim_end().to_string())
+            })?;
+
+        Some(Self {
+            pid,
+            name,
+            argv,
+            cwd: std::fs::read_link(proc.join("cwd")).ok(),
+        })
+    }
+}
+
+/// Signals for [`TerminalBackend::send_signal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    /// `SIGHUP`, as when the terminal closes.
+    Hangup,
+    /// `SIGINT`, what Ctrl+C sends.
+    Interrupt,
+    /// `SIGQUIT`, what Ctrl+\\ sends.
+    Quit,
+    /// `SIGTSTP`, what Ctrl+Z sends.
+    Suspend,
+    /// `SIGCONT`, resumes a suspended job.
+    Continue,
+    /// `SIGTERM`.
+    Terminate,
+    /// `SIGKILL`, which cannot be caught.
+    Kill,
+    /// Any other signal, by number.
+    Other(i32),
+}
+
+#[cfg(unix)]
+impl Signal {
+    pub fn number(self) -> i32 {
+        match self {
+            Self::Hangup => libc::SIGHUP,
+            Self::Interrupt => libc::SIGINT,
+            Self::Quit => libc::SIGQUIT,
+            Self::Suspend => libc::SIGTSTP,
+            Self::Continue => libc::SIGCONT,
+            Self::Terminate => libc::SIGTERM,
+            Self::Kill => libc::SIGKILL,
+            Self::Other(number) => number,
+        }
+    }
+
+    pub fn from_number(number: i32) -> Self {
+        match number {
+            libc::SIGHUP => Self::Hangup,
+            libc::SIGINT => Self::Interrupt,
+            libc::SIGQUIT => Self::Quit,
+            libc::SIGTSTP => Self::Suspend,
+            libc::SIGCONT => Self::Continue,
+            libc::SIGTERM => Self::Terminate,
+            libc::SIGKILL => Self::Kill,
+            number => Self::Other(number),
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hangup => f.write_str("SIGHUP"),
+            Self::Interrupt => f.write_str("SIGINT"),
+            Self::Quit => f.write_str("SIGQUIT"),
+            Self::Suspend => f.write_str("SIGTSTP"),
+            Self::Continue => f.write_str("SIGCONT"),
+            Self::Terminate => f.write_str("SIGTERM"),
+            Self::Kill => f.write_str("SIGKILL"),
+            Self::Other(number) => write!(f, "signal {number}"),
+        }
+    }
+}
+
+/// Who [`TerminalBackend::send_signal`] signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignalTarget {
+    /// The process the backend started, usually the shell.
+    Shell,
+    /// Every process of the job in the foreground, e.g. all of
+    /// `make | less`. This is the shell itself while it waits at the
+    /// prompt.
+    ForegroundGroup,
+}
+
+/// How the process behind a terminal ended, sent as
+/// [`crate::PtyEvent::ChildExit`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ExitStatus {
+    /// Exit code, if the process exited by itself.
+    pub code: Option<i32>,
+    /// Signal that terminated the process.
+    pub signal: Option<Signal>,
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Decodes a status as returned by `waitpid`.
+    #[cfg(unix)]
+    pub(crate) fn from_wait_status(status: i32) -> Self {
+        Self {
+            code: libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)),
+            signal: libc::WIFSIGNALED(status)
+                .then(|| Signal::from_number(libc::WTERMSIG(status))),
+        }
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status)
+            .map(Signal::from_number);
+        #[cfg(not(unix))]
+        let signal = None;
+        Self {
+            code: status.code(),
+            signal,
+        }
+    }
+}
+
+impl TerminalBackend {
+    /// Sends `signal` to the shell or to the foreground job, for hosts
+    /// that offer "Interrupt", "Suspend" or "Terminate job" buttons. On
+    /// Windows only [`Signal::Terminate`] and [`Signal::Kill`] of the shell
+    /// are supported, both end it.
+    pub fn send_signal(
+        &self,
+        signal: Signal,
+        target: SignalTarget,
+    ) -> Result<()> {
+        let (pid, group) = match target {
+            SignalTarget::Shell => (self.pty_id, false),
+            SignalTarget::ForegroundGroup => {
+                (self.foreground_process_group().unwrap_or(0), true)
+            },
+        };
+        if pid == 0 {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "no process to signal",
+            ));
+        }
+        kill(pid, group, signal)
+    }
+
+    /// Process group in the foreground of the terminal, the shell's own
+    /// while it waits at the prompt.
+    pub fn foreground_process_group(&self) -> Option<u32> {
+        foreground_group(self.terminal_device.as_ref()?)
+    }
+
+    /// Leader of the foreground process group.
+    pub fn foreground_process(&self) -> Option<ProcessInfo> {
+        ProcessInfo::read(self.foreground_process_group()?)
+    }
+
+    /// Whether a program started by the shell, rather than the shell
+    /// itself, has the terminal. Hosts can ask before closing then.
+    pub fn has_foreground_job(&self) -> bool {
+        self.foreground_process_group()
+            .is_some_and(|group| self.pty_id != 0 && group != self.pty_id)
+    }
+
+    /// Fills in a title template such as `{process} — {cwd_basename}`.
+    ///
+    /// - `{process}`: name of the foreground program, or of the shell
+    /// - `{args}`: its arguments
+    /// - `{cwd}`: working directory, with the home directory as `~`
+    /// - `{cwd_basename}`: last component of the working directory
+    ///
+    /// The working directory is the foreground program's while a job runs
+    /// and [`Self::current_dir`] otherwise. Unknown values are empty.
+    pub fn format_title(&self, template: &str) -> String {
+        let process = self.foreground_process();
+        let name = process
+            .as_ref()
+            .map(|process| process.name.clone())
+            .or_else(|| {
+                let shell = &self.spawn_settings.as_ref()?.shell;
+                file_name(Path::new(shell))
+            })
+            .unwrap_or_default();
+        let args = process
+            .as_ref()
+            .map(|process| process.argv.iter().skip(1).cloned())
+            .map(|args| args.collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+        let cwd = match process {
+            Some(process) if self.has_foreground_job() => process.cwd,
+            _ => self.current_dir(),
+        };
+        let cwd_basename = cwd
+            .as_deref()
+            .and_then(|cwd| file_name(cwd).or_else(|| Some(display(cwd))))
+            .unwrap_or_default();
+        let cwd = cwd.as_deref().map(abbreviate_home).unwrap_or_default();
+
+        template
+            .replace("{process}", &name)
+            .replace("{args}", &args)
+            .replace("{cwd_basename}", &cwd_basename)
+            .replace("{cwd}", &cwd)
+    }
+}
+
+#[cfg(unix)]
+fn foreground_group(device: &File) -> Option<u32> {
+    use std::os::fd::AsRawFd;
+
+    let group = unsafe { libc::tcgetpgrp(device.as_raw_fd()) };
+    (group > 0).then_some(group as u32)
+}
+
+#[cfg(not(unix))]
+fn foreground_group(_device: &File) -> Option<u32> {
+    None
+}
+
+/// Signals process `pid`, or its whole process group.
+#[cfg(unix)]
+pub(crate) fn kill(pid: u32, group: bool, signal: Signal) -> Result<()> {
+    let pid = if group { -(pid as i32) } else { pid as i32 };
+    if unsafe { libc::kill(pid, signal.number()) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub(crate) fn kill(pid: u32, _group: bool, signal: Signal) -> Result<()> {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, TerminateProcess, PROCESS_TERMINATE,
+    };
+
+    if !matches!(signal, Signal::Terminate | Signal::Kill) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{signal:?} is not supported on Windows"),
+        ));
+    }
+    unsafe {
+        let handle = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if handle.is_null() {
+            return Err(Error::last_os_error());
+        }
+        let terminated = TerminateProcess(handle, 1);
+        let _ = CloseHandle(handle);
+        if terminated == 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    Some(path.file_name()?.to_string_lossy().into_owned())
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn abbreviate_home(path: &Path) -> String {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match home.and_then(|home| path.strip_prefix(home).ok().map(display)) {
+        Some(rest) if rest.is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{rest}"),
+        None => display(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reports_the_foreground_job() {
+        use crate::{BackendSettings, HeadlessTerminal};
+        use std::time::{Duration, Instant};
+
+        let settings = BackendSettings {
+            shell: "/bin/sh".to_string(),
+            args: vec!["-i".to_string()],
+            working_directory: Some("/tmp".into()),
+            ..BackendSettings::default()
+        };
+        let mut terminal = HeadlessTerminal::new(settings, 40, 5).unwrap();
+        let timeout = Duration::from_secs(5);
+        terminal.backend().wait_for_regex(r"[$#]", timeout).unwrap();
+        let title = terminal.backend().format_title("{process} — {cwd}");
+        assert_eq!(title, "sh — /tmp");
+        assert!(!terminal.backend().has_foreground_job());
+
+        terminal.write("sleep 30\r");
+        let deadline = Instant::now() + timeout;
+        while !terminal.backend().has_foreground_job() {
+            assert!(Instant::now() < deadline, "sleep never started");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let process = terminal.backend().foreground_process().unwrap();
+        assert_eq!(process.argv, ["sleep", "30"]);
+        assert_eq!(
+            terminal
+                .backend()
+                .format_title("{process} {args} — {cwd_basename}"),
+            "sleep 30 — tmp"
+        );
+        terminal.write("\x03");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn signals_the_job_and_the_shell() {
+        use crate::{HeadlessTerminal, Signal, SignalTarget};
+        use std::time::{Duration, Instant};
+
+        let mut terminal =
+            HeadlessTerminal::run("/bin/sh", &["-i"], 40, 5).unwrap();
+        let timeout = Duration::from_secs(5);
+        terminal.backend().wait_for_regex(r"[$#]", timeout).unwrap();
+        terminal.write("sleep 30\r");
+        let wait_for_job = |terminal: &HeadlessTerminal, running: bool| {
+            let deadline = Instant::now() + timeout;
+            while terminal.backend().has_foreground_job() != running {
+                assert!(Instant::now() < deadline, "job never changed");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        wait_for_job(&terminal, true);
+
+        terminal
+            .backend()
+            .send_signal(Signal::Interrupt, SignalTarget::ForegroundGroup)
+            .unwrap();
+        wait_for_job(&terminal, false);
+        assert!(terminal.is_running());
+
+        terminal
+            .backend()
+            .send_signal(Signal::Kill, SignalTarget::Shell)
+            .unwrap();
+        assert!(terminal.wait_for_exit(timeout));
+        let status = terminal.exit_status().unwrap();
+        assert_eq!((status.code, status.signal), (None, Some(Signal::Kill)));
+    }
+}
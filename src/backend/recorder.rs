@@ -0,0 +1,206 @@
+//! asciicast v2 session recording.
+//!
+//! See <https://docs.asciinema.org/manual/asciicast/v2/> for the format: a
+//! JSON header line followed by one `[time, code, data]` line per event.
+
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordingSettings {
+    pub path: PathBuf,
+    /// Also record what is written to the PTY. Off by default since input
+    /// contains typed passwords.
+    pub record_input: bool,
+    pub title: Option<String>,
+}
+
+impl RecordingSettings {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            record_input: false,
+            title: None,
+        }
+    }
+}
+
+pub(crate) type SharedRecorder = Arc<Mutex<Option<AsciicastRecorder>>>;
+
+pub(crate) struct AsciicastRecorder {
+    writer: BufWriter<File>,
+    started: Instant,
+    record_input: bool,
+    /// Tail of the last output chunk that ends inside a UTF-8 sequence.
+    pending_output: Vec<u8>,
+}
+
+impl AsciicastRecorder {
+    pub(crate) fn create(
+        settings: &RecordingSettings,
+        columns: usize,
+        lines: usize,
+        env: &[(&str, String)],
+    ) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(&settings.path)?);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        let mut header = format!(
+            "{{\"version\": 2, \"width\": {columns}, \"height\": {lines}, \
+             \"timestamp\": {timestamp}"
+        );
+        if let Some(title) = &settings.title {
+            header.push_str(", \"title\": ");
+            push_json_string(&mut header, title);
+        }
+        if !env.is_empty() {
+            header.push_str(", \"env\": {");
+            for (index, (key, value)) in env.iter().enumerate() {
+                if index > 0 {
+                    header.push_str(", ");
+                }
+                push_json_string(&mut header, key);
+                header.push_str(": ");
+                push_json_string(&mut header, value);
+            }
+            header.push('}');
+        }
+        header.push('}');
+        writeln!(writer, "{header}")?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+            record_input: settings.record_input,
+            pending_output: Vec::new(),
+        })
+    }
+
+    pub(crate) fn output(&mut self, bytes: &[u8]) -> Result<()> {
+        self.pending_output.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending_output) {
+            Ok(_) => self.pending_output.len(),
+            // Keep an incomplete trailing sequence for the next chunk.
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => self.pending_output.len(),
+        };
+
+        let chunk: Vec<u8> = self.pending_output.drain(..valid).collect();
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.event("o", &String::from_utf8_lossy(&chunk))
+    }
+
+    pub(crate) fn input(&mut self, bytes: &[u8]) -> Result<()> {
+        if !self.record_input {
+            return Ok(());
+        }
+        self.event("i", &String::from_utf8_lossy(bytes))
+    }
+
+    pub(crate) fn resize(
+        &mut self,
+        columns: usize,
+        lines: usize,
+    ) -> Result<()> {
+        self.event("r", &format!("{columns}x{lines}"))
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        if !self.pending_output.is_empty() {
+            let tail = std::mem::take(&mut self.pending_output);
+            self.event("o", &String::from_utf8_lossy(&tail))?;
+        }
+        self.writer.flush()
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> Result<()> {
+        let mut line = format!(
+            "[{:.6}, \"{code}\", ",
+            self.started.elapsed().as_secs_f64()
+        );
+        push_json_string(&mut line, data);
+        line.push(']');
+        writeln!(self.writer, "{line}")?;
+        self.writer.flush()
+    }
+}
+
+/// Records `bytes` when a recording is running. A failing recorder is
+/// stopped instead of failing the terminal.
+pub(crate) fn record(
+    recorder: &SharedRecorder,
+    event: impl FnOnce(&mut AsciicastRecorder) -> Result<()>,
+) {
+    let Ok(mut recorder) = recorder.lock() else {
+        return;
+    };
+    if let Some(active) = recorder.as_mut() {
+        if let Err(err) = event(active) {
+            eprintln!("asciicast recording stopped: {err}");
+            *recorder = None;
+        }
+    }
+}
+
+fn push_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsciicastRecorder, RecordingSettings};
+
+    #[test]
+    fn writes_asciicast_v2() {
+        let path = std::env::temp_dir()
+            .join(format!("egui_term_cast_{}.cast", std::process::id()));
+        let mut settings = RecordingSettings::new(&path);
+        settings.title = Some("demo \"1\"".to_string());
+
+        let env = [("TERM", "xterm-256color".to_string())];
+        let mut recorder =
+            AsciicastRecorder::create(&settings, 80, 24, &env).unwrap();
+        recorder.output(b"hi\r\n\x1b[1m\xe2\x82").unwrap();
+        recorder.output(b"\xac").unwrap();
+        recorder.input(b"secret").unwrap();
+        recorder.resize(100, 30).unwrap();
+        recorder.finish().unwrap();
+
+        let cast = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = cast.lines().collect();
+        assert!(lines[0].starts_with(
+            "{\"version\": 2, \"width\": 80, \"height\": 24, \"timestamp\": "
+        ));
+        assert!(lines[0].ends_with(
+            ", \"title\": \"demo \\\"1\\\"\", \
+             \"env\": {\"TERM\": \"xterm-256color\"}}"
+        ));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with(", \"o\", \"hi\\r\\n\\u001b[1m\"]"));
+        assert!(lines[2].ends_with(", \"o\", \"\u{20ac}\"]"));
+        assert!(lines[3].ends_with(", \"r\", \"100x30\"]"));
+    }
+}
@@ -1,16 +1,324 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+use alacritty_terminal::term::{self, Osc52, SEMANTIC_ESCAPE_CHARS};
+
+use super::process::Signal;
 use crate::types::Size;
 
-const DEFAULT_SHELL: &str = "/bin/bash";
+/// Shell of the user: `$SHELL`, the login shell of the passwd entry or
+/// `/bin/sh`, whichever exists first. `%COMSPEC%` or `cmd.exe` on Windows.
+pub fn default_shell() -> String {
+    #[cfg(unix)]
+    {
+        let exists = |shell: &String| {
+            !shell.is_empty() && std::path::Path::new(shell).exists()
+        };
+        std::env::var("SHELL")
+            .ok()
+            .filter(exists)
+            .or_else(|| passwd_shell().filter(exists))
+            .unwrap_or_else(|| "/bin/sh".to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string())
+    }
+}
+
+#[cfg(unix)]
+fn passwd_shell() -> Option<String> {
+    let mut entry = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0; 4096];
+    let res = unsafe {
+        libc::getpwuid_r(
+            libc::getuid(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if res != 0 || result.is_null() || entry.pw_shell.is_null() {
+        return None;
+    }
+    let shell = unsafe { std::ffi::CStr::from_ptr(entry.pw_shell) };
+    Some(shell.to_string_lossy().into_owned())
+}
+
+/// Patterns tried by smart selection, in priority order: URLs, file paths
+/// with optional `:line:col`, IP addresses and quoted strings.
+pub const DEFAULT_SMART_SELECTION_PATTERNS: &[&str] = &[
+    super::URL_REGEX,
+    r"(?:~|\.{1,2}|[\w.\-+@]+)?(?:/[\w.\-+@]+)+(?::\d+){0,2}",
+    r"[\w.\-+@]+\.\w+:\d+(?::\d+)?",
+    r"(?:\d{1,3}\.){3}\d{1,3}(?:/\d{1,2}|:\d{1,5})?",
+    r"[0-9a-fA-F]{1,4}(?::[0-9a-fA-F]{0,4}){2,7}",
+    r#""[^"\n]*""#,
+    r"'[^'\n]*'",
+    r"`[^`\n]*`",
+];
+
+/// Options of the terminal emulator itself. A running backend can change
+/// them with [`crate::BackendCommand::UpdateConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalConfig {
+    /// Number of lines kept in the scrollback history.
+    pub scrolling_history: usize,
+    /// Characters that end a word for double-click (semantic) selection.
+    pub semantic_escape_chars: String,
+    /// Lets applications enable the kitty keyboard protocol modes. Keys are
+    /// still encoded through the bindings layout.
+    pub kitty_keyboard: bool,
+    /// Which OSC 52 clipboard requests are forwarded as
+    /// [`crate::PtyEvent::ClipboardStore`] / [`crate::PtyEvent::ClipboardLoad`].
+    pub osc52: Osc52,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        Self {
+            scrolling_history: 10_000,
+            semantic_escape_chars: SEMANTIC_ESCAPE_CHARS.to_string(),
+            kitty_keyboard: false,
+            osc52: Osc52::default(),
+        }
+    }
+}
+
+impl From<&TerminalConfig> for term::Config {
+    fn from(config: &TerminalConfig) -> Self {
+        Self {
+            scrolling_history: config.scrolling_history,
+            semantic_escape_chars: config.semantic_escape_chars.clone(),
+            kitty_keyboard: config.kitty_keyboard,
+            osc52: config.osc52,
+            ..Self::default()
+        }
+    }
+}
+
+/// Where [`OverflowSettings`] keeps lines that fell off the grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverflowStorage {
+    /// Compressed ring buffer in memory.
+    Memory,
+    /// Compressed append-only files. Segments are written to `<path>.0`,
+    /// `<path>.1`, ... and removed when evicted or when the backend drops.
+    File(PathBuf),
+}
+
+/// Unlimited scrollback: lines scrolled past
+/// [`TerminalConfig::scrolling_history`] are compressed into an overflow
+/// store instead of being dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverflowSettings {
+    pub storage: OverflowStorage,
+    /// Maximum size of the compressed store. The oldest lines are evicted
+    /// once it is exceeded.
+    pub budget_bytes: usize,
+    /// Extra grid lines kept on top of `scrolling_history` so that output
+    /// produced between two spills is not lost.
+    pub spill_margin: usize,
+}
+
+impl Default for OverflowSettings {
+    fn default() -> Self {
+        Self {
+            storage: OverflowStorage::Memory,
+            budget_bytes: 64 * 1024 * 1024,
+            spill_margin: 4096,
+        }
+    }
+}
+
+/// How [`crate::TerminalBackend::shutdown`] ends a session.
+///
+/// Each signal goes to the shell's process group, followed by `grace` to
+/// exit. The default hangs up, which lets shells save their history and
+/// editors write swap files, and kills what is left after two seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownPolicy {
+    pub signals: Vec<Signal>,
+    pub grace: Duration,
+}
+
+impl Default for ShutdownPolicy {
+    fn default() -> Self {
+        Self {
+            signals: vec![Signal::Hangup, Signal::Kill],
+            grace: Duration::from_secs(2),
+        }
+    }
+}
+
+/// What happens to the terminal when its shell exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExitBehavior {
+    /// The host gets [`crate::PtyEvent::Exit`], usually to close the tab.
+    #[default]
+    Close,
+    /// The terminal stays open with an exit banner instead of sending
+    /// [`crate::PtyEvent::Exit`]. Enter starts the shell again, see
+    /// [`crate::TerminalBackend::respawn`].
+    Hold,
+}
+
+/// Input written to a shell once it is ready, see
+/// [`BackendSettings::startup_input`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartupInput {
+    /// A command line, sent with Enter.
+    Command(String),
+    /// Bytes written as they are.
+    Raw(Vec<u8>),
+}
+
+/// When a new shell counts as ready for [`BackendSettings::startup_input`].
+/// The input is written after [`BackendSettings::startup_timeout`] at the
+/// latest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StartupReady {
+    /// At the first OSC 133 prompt mark, once the init scripts ran. Needs
+    /// the snippets of the `shell-integration` directory.
+    #[default]
+    Prompt,
+    /// At the first output.
+    Output,
+}
+
+/// Variables [`EnvInherit::Allowlist`] is meant to start from: the user's
+/// identity, locale, display and agent sockets.
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "PATH",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "TZ",
+    "TMPDIR",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "SSH_AUTH_SOCK",
+];
+
+/// Which variables of the host's environment a shell inherits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EnvInherit {
+    #[default]
+    All,
+    /// Only these names. A trailing `*` matches a prefix, as in `LC_*`.
+    Allowlist(Vec<String>),
+    /// Nothing, the shell only gets what the settings set.
+    None,
+}
+
+/// Computes variables for every shell a backend spawns, called with the
+/// id of the backend.
+pub type EnvOverrideHook =
+    Arc<dyn Fn(u64) -> HashMap<String, String> + Send + Sync>;
+
+/// How the environment of a shell is built, in this order: the inherited
+/// variables without `unset`, [`BackendSettings::env`], the
+/// `TERM_PROGRAM` identity and `overrides`.
+///
+/// On Windows the shell always inherits everything. Spawning fails there
+/// with [`std::io::ErrorKind::Unsupported`] unless `inherit` is
+/// [`EnvInherit::All`] and `unset` is empty.
+#[derive(Clone)]
+pub struct EnvPolicy {
+    pub inherit: EnvInherit,
+    /// Inherited variables to drop, such as API tokens of the host.
+    pub unset: Vec<String>,
+    /// Name and version, sent as `TERM_PROGRAM` and `TERM_PROGRAM_VERSION`.
+    pub term_program: Option<(String, String)>,
+    /// Per-session variables, computed each time a shell is spawned.
+    pub overrides: Option<EnvOverrideHook>,
+}
+
+impl Default for EnvPolicy {
+    fn default() -> Self {
+        Self {
+            inherit: EnvInherit::All,
+            unset: Vec::new(),
+            term_program: Some((
+                "egui_term".to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            )),
+            overrides: None,
+        }
+    }
+}
+
+impl fmt::Debug for EnvPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvPolicy")
+            .field("inherit", &self.inherit)
+            .field("unset", &self.unset)
+            .field("term_program", &self.term_program)
+            .field("overrides", &self.overrides.is_some())
+            .finish()
+    }
+}
+
+impl EnvPolicy {
+    #[cfg(unix)]
+    fn allows(&self, name: &str) -> bool {
+        match &self.inherit {
+            EnvInherit::All => true,
+            EnvInherit::Allowlist(names) => {
+                names.iter().any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => pattern == name,
+                })
+            },
+            EnvInherit::None => false,
+        }
+    }
+
+    /// Variables set on top of the inherited ones for backend `id`.
+    pub(crate) fn variables(
+        &self,
+        env: &HashMap<String, String>,
+        id: u64,
+    ) -> HashMap<String, String> {
+        let mut variables = env.clone();
+        if let Some((name, version)) = &self.term_program {
+            variables.insert("TERM_PROGRAM".to_string(), name.clone());
+            variables
+                .insert("TERM_PROGRAM_VERSION".to_string(), version.clone());
+        }
+        if let Some(overrides) = &self.overrides {
+            variables.extend(overrides(id));
+        }
+        variables
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct BackendSettings {
+    /// Program to run, [`default_shell`] by default.
     pub shell: String,
     pub args: Vec<String>,
+    /// Starts the shell as a login shell, with a `-` in front of its
+    /// name in argv\[0\] like `login` does. Not supported on Windows,
+    /// where spawning fails with [`std::io::ErrorKind::Unsupported`].
+    pub login_shell: bool,
     pub working_directory: Option<PathBuf>,
+    /// Variables set for the shell, `TERM` and `COLORTERM` by default.
     pub env: HashMap<String, String>,
+    /// What else ends up in the shell's environment, see [`EnvPolicy`].
+    pub env_policy: EnvPolicy,
     /// Initial terminal layout size (width, height in pixels). When provided
     /// together with [`initial_cell_metrics`] the PTY is spawned at the correct
     /// column/row count from the very beginning, avoiding a resize on the first
@@ -19,6 +327,27 @@ pub struct BackendSettings {
     /// Initial font cell metrics (cell width, cell height in pixels) used to
     /// compute the initial column/row count. See [`initial_layout_size`].
     pub initial_cell_metrics: Option<Size>,
+    pub terminal_config: TerminalConfig,
+    /// When enabled, double-click first selects the URL, path, IP address or
+    /// quoted string under the pointer and falls back to word selection.
+    pub smart_selection: bool,
+    /// Regexes used by smart selection, see
+    /// [`DEFAULT_SMART_SELECTION_PATTERNS`].
+    pub smart_selection_patterns: Vec<String>,
+    /// Keeps scrollback beyond the grid history in a compressed store, see
+    /// [`OverflowSettings`].
+    pub overflow: Option<OverflowSettings>,
+    /// How the session ends when the backend shuts down or drops.
+    pub shutdown: ShutdownPolicy,
+    pub exit_behavior: ExitBehavior,
+    /// Commands or raw input for a new shell, such as activating a venv or
+    /// an `ssh` to some host, written once it is ready instead of racing
+    /// its init scripts. Written again by
+    /// [`crate::TerminalBackend::respawn`].
+    pub startup_input: Vec<StartupInput>,
+    pub startup_ready: StartupReady,
+    /// Longest wait for the shell to get ready.
+    pub startup_timeout: Duration,
 }
 
 impl Default for BackendSettings {
@@ -28,12 +357,285 @@ impl Default for BackendSettings {
         env.insert("COLORTERM".to_string(), "truecolor".to_string());
 
         Self {
-            shell: DEFAULT_SHELL.to_string(),
+            shell: default_shell(),
             args: vec![],
+            login_shell: false,
             working_directory: None,
             env,
+            env_policy: EnvPolicy::default(),
             initial_layout_size: None,
             initial_cell_metrics: None,
+            terminal_config: TerminalConfig::default(),
+            smart_selection: false,
+            smart_selection_patterns: DEFAULT_SMART_SELECTION_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            overflow: None,
+            shutdown: ShutdownPolicy::default(),
+            exit_behavior: ExitBehavior::default(),
+            startup_input: Vec::new(),
+            startup_ready: StartupReady::default(),
+            startup_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl BackendSettings {
+    /// Command that starts the shell of backend `id`.
+    #[cfg(unix)]
+    pub(crate) fn shell_command(&self, id: u64) -> std::process::Command {
+        use std::os::unix::process::CommandExt;
+
+        let mut command = std::process::Command::new(&self.shell);
+        command.args(&self.args);
+        let policy = &self.env_policy;
+        if policy.inherit != EnvInherit::All {
+            command.env_clear().envs(std::env::vars_os().filter(
+                |(name, _)| {
+                    name.to_str().is_some_and(|name| policy.allows(name))
+                },
+            ));
+        }
+        for name in &policy.unset {
+            command.env_remove(name);
+        }
+        // Startup notification of the host, not meant for the shell.
+        command
+            .env_remove("XDG_ACTIVATION_TOKEN")
+            .env_remove("DESKTOP_STARTUP_ID");
+        command.envs(policy.variables(&self.env, id));
+        if self.login_shell {
+            let name = std::path::Path::new(&self.shell)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.shell.clone());
+            command.arg0(format!("-{name}"));
         }
+        // Like alacritty, a missing directory is not an error.
+        if let Some(dir) =
+            self.working_directory.as_ref().filter(|dir| dir.is_dir())
+        {
+            command.current_dir(dir);
+        }
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DEFAULT_SMART_SELECTION_PATTERNS;
+    use alacritty_terminal::term::search::RegexSearch;
+
+    #[test]
+    fn running_backend_follows_the_terminal_config() {
+        use super::{BackendSettings, TerminalConfig};
+        use crate::{BackendCommand, ChannelSource, TerminalBackend};
+        use alacritty_terminal::term;
+        use std::ops::Range;
+        use std::time::Duration;
+
+        let config = TerminalConfig {
+            scrolling_history: 50,
+            semantic_escape_chars: ",".to_string(),
+            kitty_keyboard: true,
+            ..TerminalConfig::default()
+        };
+        let options = term::Config::from(&config);
+        assert_eq!(options.scrolling_history, 50);
+        assert_eq!(options.semantic_escape_chars, ",");
+        assert!(options.kitty_keyboard);
+
+        let (source, peer) = ChannelSource::new();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut backend = TerminalBackend::with_source(
+            0,
+            egui::Context::default(),
+            sender,
+            Box::new(source),
+            BackendSettings::default(),
+        )
+        .unwrap();
+        let print = |backend: &mut TerminalBackend, lines: Range<usize>| {
+            let last = format!("line {}$", lines.end - 1);
+            let text: String =
+                lines.map(|line| format!("\r\nline {line}")).collect();
+            peer.output.send(text.into_bytes()).unwrap();
+            let timeout = Duration::from_secs(5);
+            backend
+                .wait_for_regex(&format!("(?m){last}"), timeout)
+                .unwrap();
+            let content = backend.sync();
+            content.total_lines - content.terminal_size.num_lines as usize
+        };
+
+        // The first line stays empty.
+        let screen_lines = backend.sync().terminal_size.num_lines as usize;
+        assert_eq!(print(&mut backend, 0..200), 201 - screen_lines);
+        backend.process_command(BackendCommand::UpdateConfig(config.clone()));
+        let hot_history = |backend: &TerminalBackend| {
+            backend
+                .commands
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .hot_history
+        };
+        assert_eq!(hot_history(&backend), 50);
+        assert_eq!(backend.sync().total_lines - screen_lines, 50);
+        assert_eq!(print(&mut backend, 200..300), 50);
+
+        backend.process_command(BackendCommand::UpdateConfig(TerminalConfig {
+            scrolling_history: 1000,
+            ..config
+        }));
+        assert_eq!(hot_history(&backend), 1000);
+        assert_eq!(print(&mut backend, 300..600), 350);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn builds_the_shell_environment() {
+        use super::{BackendSettings, EnvInherit, EnvPolicy};
+        use crate::{BackendSource, PipeSource};
+        use std::collections::HashMap;
+        use std::io::Read;
+        use std::sync::Arc;
+
+        let env_of = |env_policy: EnvPolicy| {
+            let settings = BackendSettings {
+                shell: "/usr/bin/env".to_string(),
+                env_policy,
+                ..BackendSettings::default()
+            };
+            let command = settings.shell_command(7);
+            let mut source = PipeSource::spawn(command).unwrap();
+            let mut output = String::new();
+            let mut reader = source.take_reader().unwrap();
+            reader.read_to_string(&mut output).unwrap();
+            output
+                .lines()
+                .filter_map(|line| line.trim_end().split_once('='))
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let env = env_of(EnvPolicy {
+            unset: vec!["HOME".to_string()],
+            ..EnvPolicy::default()
+        });
+        assert!(env.contains_key("PATH") && !env.contains_key("HOME"));
+        assert_eq!(env["TERM"], "xterm-256color");
+        assert_eq!(env["TERM_PROGRAM"], "egui_term");
+
+        let env = env_of(EnvPolicy {
+            inherit: EnvInherit::Allowlist(vec!["PAT*".to_string()]),
+            overrides: Some(Arc::new(|id| {
+                HashMap::from([("SESSION".to_string(), id.to_string())])
+            })),
+            ..EnvPolicy::default()
+        });
+        assert!(env.contains_key("PATH") && !env.contains_key("HOME"));
+        assert_eq!(env["SESSION"], "7");
+
+        let env = env_of(EnvPolicy {
+            inherit: EnvInherit::None,
+            term_program: None,
+            ..EnvPolicy::default()
+        });
+        let mut names = env.keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["COLORTERM", "TERM"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn default_shell_exists() {
+        let shell = super::default_shell();
+        assert!(std::path::Path::new(&shell).exists(), "{shell}");
+    }
+
+    #[test]
+    fn default_smart_selection_patterns_compile() {
+        for pattern in DEFAULT_SMART_SELECTION_PATTERNS {
+            assert!(RegexSearch::new(pattern).is_ok(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn double_click_selects_smart_matches_and_falls_back_to_words() {
+        use super::{BackendSettings, TerminalConfig};
+        use crate::backend::SelectionType;
+        use crate::{BackendCommand, ChannelSource, TerminalBackend};
+        use alacritty_terminal::index::{Column, Line, Point};
+        use std::time::Duration;
+
+        let (source, peer) = ChannelSource::new();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut backend = TerminalBackend::with_source(
+            0,
+            egui::Context::default(),
+            sender,
+            Box::new(source),
+            BackendSettings {
+                terminal_config: TerminalConfig {
+                    semantic_escape_chars: ",".to_string(),
+                    ..TerminalConfig::default()
+                },
+                smart_selection: true,
+                ..BackendSettings::default()
+            },
+        )
+        .unwrap();
+        let lines = [
+            "open https://example.com/a?b=1 now",
+            "at src/main.rs:12:5 here",
+            "host 192.168.1.10:8080 up",
+            "say \"hello world\" twice",
+            "alpha,beta gamma,delta",
+        ];
+        peer.output.send(lines.join("\r\n").into_bytes()).unwrap();
+        let timeout = Duration::from_secs(5);
+        backend.wait_for_regex("gamma,delta", timeout).unwrap();
+
+        // Cells are one pixel wide and high.
+        let mut double_click = |line: usize, column: usize| {
+            backend.process_command(BackendCommand::SelectStart(
+                SelectionType::Semantic,
+                column as f32 + 0.5,
+                line as f32 + 0.5,
+            ));
+            let range = backend.sync().selectable_range.unwrap();
+            (range.start, range.end, backend.selectable_content())
+        };
+        let point =
+            |line: i32, column: usize| Point::new(Line(line), Column(column));
+
+        assert_eq!(
+            double_click(0, 15),
+            (
+                point(0, 5),
+                point(0, 29),
+                "https://example.com/a?b=1".into()
+            )
+        );
+        assert_eq!(
+            double_click(1, 5),
+            (point(1, 3), point(1, 18), "src/main.rs:12:5".into())
+        );
+        assert_eq!(
+            double_click(2, 10),
+            (point(2, 5), point(2, 21), "192.168.1.10:8080".into())
+        );
+        assert_eq!(
+            double_click(3, 12),
+            (point(3, 4), point(3, 16), "\"hello world\"".into())
+        );
+        // Only the configured separator ends a word, not the space.
+        assert_eq!(
+            double_click(4, 8),
+            (point(4, 6), point(4, 15), "beta gamma".into())
+        );
     }
 }
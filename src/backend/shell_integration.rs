@@ -0,0 +1,1249 @@
+//! FinalTerm / OSC 133 shell integration.
+//!
+//! Shells that emit `OSC 133 ; A` (prompt start), `B` (command start),
+//! `C` (command executed) and `D [; exit code]` (command finished) let the
+//! terminal know where prompts, commands and their output are. Snippets for
+//! bash, zsh and fish are in the `shell-integration` directory. They also
+//! report the working directory with `OSC 7 ; file://host/path`.
+//!
+//! Marks are stored against absolute line numbers: the number of lines
+//! that scrolled off the top of the screen plus the screen line. To keep
+//! that count exact the grid history is trimmed here after every parsed
+//! piece of output, instead of letting alacritty drop lines silently.
+//!
+//! Folding the output of a finished command moves its lines out of the grid
+//! into the command record and leaves a summary line in their place, so
+//! the view and search skip them. Output that already left the grid is
+//! folded in the overflow store, or is gone without one. Resizing the
+//! terminal unfolds the grid first, so that the lines reflow with the rest
+//! of the history, and folds it again afterwards.
+
+use alacritty_terminal::grid::{Dimensions, Row};
+use alacritty_terminal::index::{Column, Line, Point};
+use alacritty_terminal::term::cell::Cell;
+use alacritty_terminal::term::{Term, TermMode};
+use alacritty_terminal::vte::ansi::{Color, NamedColor};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::export::row_text;
+use super::overflow::OverflowStore;
+
+/// Lines the grid history may grow past its limit between two trims.
+/// Output is parsed in pieces of this many bytes, so no line is dropped
+/// by the grid itself.
+pub(crate) const TRACKING_MARGIN: usize = 4096;
+
+/// Most commands remembered, oldest are dropped first.
+const MAX_COMMANDS: usize = 10_000;
+
+/// Longest OSC payload inspected, enough for the paths of OSC 7.
+const MAX_OSC_LEN: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ShellMark {
+    PromptStart,
+    CommandStart,
+    CommandExecuted,
+    CommandFinished(Option<i32>),
+    /// Working directory reported with OSC 7, `None` for another host.
+    CurrentDir(Option<PathBuf>),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    #[default]
+    Ground,
+    Escape,
+    Osc,
+    OscEscape,
+    Csi {
+        private: bool,
+        first: bool,
+    },
+}
+
+/// Finds the places the terminal parser has to stop at: OSC 133 marks, OSC 7
+/// working directories and
+/// private mode changes that may switch to or from the alternate screen.
+#[derive(Debug, Default)]
+pub(crate) struct MarkScanner {
+    state: ScanState,
+    osc: Vec<u8>,
+}
+
+impl MarkScanner {
+    /// Scans `bytes` up to the end of the next interesting sequence.
+    /// Returns its end offset and the mark, `None` for a mode change. All
+    /// of `bytes` was consumed when nothing was found.
+    pub(crate) fn scan(
+        &mut self,
+        bytes: &[u8],
+    ) -> Option<(usize, Option<ShellMark>)> {
+        for (index, &byte) in bytes.iter().enumerate() {
+            if let Some(mark) = self.advance(byte) {
+                return Some((index + 1, mark));
+            }
+        }
+        None
+    }
+
+    fn advance(&mut self, byte: u8) -> Option<Option<ShellMark>> {
+        match self.state {
+            ScanState::Ground => {
+                if byte == 0x1b {
+                    self.state = ScanState::Escape;
+                }
+            },
+            ScanState::Escape => self.escape(byte),
+            ScanState::Osc => match byte {
+                0x07 => return self.finish_osc(),
+                0x1b => self.state = ScanState::OscEscape,
+                _ => {
+                    if self.osc.len() <= MAX_OSC_LEN {
+                        self.osc.push(byte);
+                    }
+                },
+            },
+            ScanState::OscEscape => {
+                if byte == b'\\' {
+                    return self.finish_osc();
+                }
+                // ESC aborts the OSC and starts a new sequence.
+                self.escape(byte);
+            },
+            ScanState::Csi { private, first } => match byte {
+                b'?' if first => {
+                    self.state = ScanState::Csi {
+                        private: true,
+                        first: false,
+                    }
+                },
+                0x20..=0x3f => {
+                    self.state = ScanState::Csi {
+                        private,
+                        first: false,
+                    }
+                },
+                0x40..=0x7e => {
+                    self.state = ScanState::Ground;
+                    if private && matches!(byte, b'h' | b'l') {
+                        return Some(None);
+                    }
+                },
+                0x1b => self.state = ScanState::Escape,
+                _ => {},
+            },
+        }
+        None
+    }
+
+    fn escape(&mut self, byte: u8) {
+        self.state = match byte {
+            b']' => {
+                self.osc.clear();
+                ScanState::Osc
+            },
+            b'[' => ScanState::Csi {
+                private: false,
+                first: true,
+            },
+            0x1b => ScanState::Escape,
+            _ => ScanState::Ground,
+        };
+    }
+
+    fn finish_osc(&mut self) -> Option<Option<ShellMark>> {
+        self.state = ScanState::Ground;
+        if self.osc.len() > MAX_OSC_LEN {
+            return None;
+        }
+        parse_mark(&self.osc).map(Some)
+    }
+}
+
+fn parse_mark(osc: &[u8]) -> Option<ShellMark> {
+    if let Some(url) = osc.strip_prefix(b"7;") {
+        return parse_file_url(url).map(ShellMark::CurrentDir);
+    }
+
+    let payload = std::str::from_utf8(osc.strip_prefix(b"133;")?).ok()?;
+    let mut params = payload.split(';');
+    let mark = match params.next()? {
+        "A" => ShellMark::PromptStart,
+        "B" => ShellMark::CommandStart,
+        "C" => ShellMark::CommandExecuted,
+        "D" => ShellMark::CommandFinished(
+            params.next().and_then(|code| code.parse().ok()),
+        ),
+        _ => return None,
+    };
+    Some(mark)
+}
+
+/// Parses `file://host/path`, the path being `None` when the host is not
+/// this machine.
+fn parse_file_url(url: &[u8]) -> Option<Option<PathBuf>> {
+    let rest = url.strip_prefix(b"file://")?;
+    let slash = rest.iter().position(|&byte| byte == b'/')?;
+    let host = std::str::from_utf8(&rest[..slash]).ok()?;
+    if !is_local_host(host) {
+        return Some(None);
+    }
+    Some(Some(path_from_bytes(percent_decode(&rest[slash..]))))
+}
+
+fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            },
+        }
+    }
+    decoded
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    let path = String::from_utf8_lossy(&bytes);
+    // `/C:/Users` names `C:/Users`.
+    let path = match path.strip_prefix('/') {
+        Some(rest) if rest.get(1..2) == Some(":") => rest,
+        _ => &path,
+    };
+    PathBuf::from(path)
+}
+
+fn is_local_host(host: &str) -> bool {
+    host.is_empty()
+        || host.eq_ignore_ascii_case("localhost")
+        || local_host_name().is_some_and(|name| name.eq_ignore_ascii_case(host))
+}
+
+#[cfg(unix)]
+fn local_host_name() -> Option<String> {
+    let mut name = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) };
+    if result != 0 {
+        return None;
+    }
+    let length = name.iter().position(|&byte| byte == 0)?;
+    String::from_utf8(name[..length].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn local_host_name() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Where a command stands, see [`CommandInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandState {
+    /// The prompt is shown and the command is being typed.
+    Editing,
+    Running,
+    /// Finished, with the exit code if the shell reported one.
+    Finished(Option<i32>),
+}
+
+/// A command intersecting the viewport, in viewport lines. Lines above the
+/// viewport are negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// Identifies the command for [`BackendCommand::SelectCommandOutput`].
+    ///
+    /// [`BackendCommand::SelectCommandOutput`]: crate::BackendCommand::SelectCommandOutput
+    pub id: u64,
+    pub prompt_line: i32,
+    /// First line of output, once the command ran.
+    pub output_line: Option<i32>,
+    /// Line of the next prompt, once the command finished.
+    pub end_line: Option<i32>,
+    pub state: CommandState,
+    /// Time the command ran for, or has been running for so far.
+    pub duration: Option<Duration>,
+    /// The output is folded into the line at `output_line`.
+    pub folded: bool,
+}
+
+/// Absolute line and column, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MarkPoint {
+    pub(crate) line: usize,
+    pub(crate) column: Column,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CommandRecord {
+    pub(crate) id: u64,
+    pub(crate) prompt: MarkPoint,
+    pub(crate) command: Option<MarkPoint>,
+    pub(crate) output: Option<MarkPoint>,
+    pub(crate) end: Option<MarkPoint>,
+    pub(crate) exit_code: Option<i32>,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    /// First line of the command as typed, read when it started.
+    command_text: Option<String>,
+    folded: bool,
+    /// Output lines taken out of the grid while folded. Output that left
+    /// the grid is folded in the overflow store.
+    fold: Option<GridFold>,
+}
+
+#[derive(Debug, Clone)]
+struct GridFold {
+    rows: Vec<Row<Cell>>,
+    /// Lines blanked right above the summary for lack of history to drop.
+    blanked: usize,
+}
+
+impl CommandRecord {
+    fn state(&self) -> CommandState {
+        match (self.output, self.end) {
+            (Some(_), Some(_)) => CommandState::Finished(self.exit_code),
+            (Some(_), None) => CommandState::Running,
+            _ => CommandState::Editing,
+        }
+    }
+
+    fn points_mut(&mut self) -> impl Iterator<Item = &mut MarkPoint> {
+        [
+            Some(&mut self.prompt),
+            self.command.as_mut(),
+            self.output.as_mut(),
+            self.end.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// Line shown in place of `lines` folded lines of output.
+    fn summary(&self, lines: usize, columns: usize) -> Row<Cell> {
+        let mut text =
+            format!("▶ {}", self.command_text.as_deref().unwrap_or(""));
+        text.push_str(&format!(" — {} line", group_thousands(lines)));
+        if lines != 1 {
+            text.push('s');
+        }
+        if let Some(code) = self.exit_code {
+            text.push_str(&format!(", exit {code}"));
+        }
+
+        let mut row = Row::<Cell>::new(columns);
+        for (column, c) in text.chars().take(columns).enumerate() {
+            let cell = &mut row[Column(column)];
+            cell.c = c;
+            cell.fg = Color::Named(NamedColor::BrightBlack);
+        }
+        row
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        let started = self.started?;
+        Some(self.finished.unwrap_or_else(Instant::now) - started)
+    }
+}
+
+/// Commands seen in the output and the line count they are stored against.
+pub(crate) struct CommandTracker {
+    /// Lines that left the top of the screen since the session started.
+    scrolled: usize,
+    /// Grid history at the last update.
+    history: usize,
+    alt_screen: bool,
+    /// Last working directory reported with OSC 7.
+    pub(crate) current_dir: Option<PathBuf>,
+    /// History kept in the grid when there is no overflow store.
+    pub(crate) hot_history: usize,
+    overflow: Option<Arc<Mutex<OverflowStore>>>,
+    commands: VecDeque<CommandRecord>,
+    next_id: u64,
+}
+
+pub(crate) type SharedCommandTracker = Arc<Mutex<CommandTracker>>;
+
+impl CommandTracker {
+    pub(crate) fn new(
+        hot_history: usize,
+        overflow: Option<Arc<Mutex<OverflowStore>>>,
+    ) -> Self {
+        Self {
+            scrolled: 0,
+            history: 0,
+            alt_screen: false,
+            current_dir: None,
+            hot_history,
+            overflow,
+            commands: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Counts the lines scrolled since the last update and trims the grid
+    /// history back to its limit.
+    pub(crate) fn update<T>(&mut self, term: &mut Term<T>) {
+        // The alternate screen has no history, the primary one is frozen.
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            self.alt_screen = true;
+            return;
+        }
+
+        let history = term.grid().history_size();
+        if self.alt_screen {
+            self.alt_screen = false;
+        } else if history > self.history {
+            self.scrolled += history - self.history;
+        }
+
+        match &self.overflow {
+            Some(overflow) => overflow.lock().unwrap().spill(term),
+            None if history > self.hot_history => {
+                let grid = term.grid_mut();
+                grid.update_history(self.hot_history);
+                grid.update_history(self.hot_history + TRACKING_MARGIN);
+            },
+            None => {},
+        }
+        self.history = term.grid().history_size();
+        self.prune();
+    }
+
+    /// Takes the current history as is, after a resize reflowed it or it
+    /// was cleared.
+    pub(crate) fn rebase<T>(&mut self, term: &Term<T>) {
+        if !term.mode().contains(TermMode::ALT_SCREEN) {
+            self.history = term.grid().history_size();
+        }
+        self.prune();
+    }
+
+    pub(crate) fn mark<T>(&mut self, mark: ShellMark, term: &Term<T>) {
+        if let ShellMark::CurrentDir(dir) = mark {
+            self.current_dir = dir;
+            return;
+        }
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            return;
+        }
+
+        let cursor = term.grid().cursor.point;
+        let point = MarkPoint {
+            line: self.scrolled + cursor.line.0.max(0) as usize,
+            column: cursor.column,
+        };
+        match mark {
+            ShellMark::PromptStart => {
+                // A command the shell did not finish ends at the next prompt.
+                if let Some(last) = self.commands.back_mut() {
+                    last.end.get_or_insert(point);
+                }
+                self.commands.push_back(CommandRecord {
+                    id: self.next_id,
+                    prompt: point,
+                    command: None,
+                    output: None,
+                    end: None,
+                    exit_code: None,
+                    started: None,
+                    finished: None,
+                    command_text: None,
+                    folded: false,
+                    fold: None,
+                });
+                self.next_id += 1;
+                if self.commands.len() > MAX_COMMANDS {
+                    self.commands.pop_front();
+                }
+            },
+            ShellMark::CommandStart => {
+                if let Some(last) = self.commands.back_mut() {
+                    last.command.get_or_insert(point);
+                }
+            },
+            ShellMark::CommandExecuted => {
+                let command_text = self
+                    .commands
+                    .back()
+                    .and_then(|last| self.grid_point(term, last.command?))
+                    .map(|start| {
+                        row_text(&term.grid()[start.line][start.column..])
+                    });
+                if let Some(last) = self.commands.back_mut() {
+                    if last.output.is_none() {
+                        last.output = Some(point);
+                        last.started = Some(Instant::now());
+                        last.command_text = command_text;
+                    }
+                }
+            },
+            ShellMark::CommandFinished(exit_code) => {
+                // Shells also finish empty command lines, which never ran.
+                if let Some(last) = self
+                    .commands
+                    .back_mut()
+                    .filter(|last| last.output.is_some() && last.end.is_none())
+                {
+                    last.end = Some(point);
+                    last.exit_code = exit_code;
+                    last.finished = Some(Instant::now());
+                }
+            },
+            ShellMark::CurrentDir(_) => {},
+        }
+    }
+
+    /// Folds the output of the command `id` into a summary line, or
+    /// unfolds it again.
+    pub(crate) fn toggle_fold<T>(&mut self, id: u64, term: &mut Term<T>) {
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            return;
+        }
+        let Some(index) = self.index(id) else {
+            return;
+        };
+
+        if self.commands[index].folded {
+            self.unfold(index, term);
+        } else {
+            self.fold(index, term);
+        }
+    }
+
+    /// Id of the most recent finished command.
+    pub(crate) fn last_finished(&self) -> Option<u64> {
+        self.commands
+            .iter()
+            .rev()
+            .find(|command| command.output.is_some() && command.end.is_some())
+            .map(|command| command.id)
+    }
+
+    /// Unfolds the output folded into summaries in the grid, so that it
+    /// reflows with the rest of it, and returns the commands to fold again
+    /// with [`Self::refold`].
+    pub(crate) fn unfold_grid<T>(&mut self, term: &mut Term<T>) -> Vec<u64> {
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            return Vec::new();
+        }
+        let unfolded: Vec<_> = self
+            .commands
+            .iter()
+            .filter(|command| {
+                command.fold.is_some()
+                    && command.output.is_some_and(|output| {
+                        self.grid_point(term, output).is_some()
+                    })
+            })
+            .map(|command| command.id)
+            .collect();
+        for &id in &unfolded {
+            if let Some(index) = self.index(id) {
+                self.unfold(index, term);
+            }
+        }
+        unfolded
+    }
+
+    /// Folds the commands `ids` again.
+    pub(crate) fn refold<T>(&mut self, ids: &[u64], term: &mut Term<T>) {
+        for &id in ids {
+            let index = self.index(id);
+            if let Some(index) = index.filter(|&i| !self.commands[i].folded) {
+                self.fold(index, term);
+            }
+        }
+    }
+
+    fn fold<T>(&mut self, index: usize, term: &mut Term<T>) {
+        let record = &self.commands[index];
+        let (Some(output), Some(end)) = (record.output, record.end) else {
+            return;
+        };
+        let id = record.id;
+        let summary = record
+            .summary(end.line.saturating_sub(output.line), term.columns());
+        // Lines that left the grid without an overflow store are gone.
+        let oldest = self.oldest_line();
+        let start = output.line.max(oldest);
+        let first_grid_line = self.scrolled.saturating_sub(self.history);
+        if start >= end.line {
+            return;
+        }
+
+        // Output above the grid is folded in the overflow store, hidden
+        // there when the summary goes into the grid.
+        let in_grid = end.line > first_grid_line;
+        let mut stored_removed = 0;
+        if start < first_grid_line {
+            let Some(overflow) = &self.overflow else {
+                return;
+            };
+            let stored = first_grid_line.min(end.line) - start;
+            let summary = (!in_grid).then(|| row_cells(&summary));
+            let Some((_, shown)) = overflow.lock().unwrap().fold(
+                id,
+                start - oldest,
+                stored,
+                summary,
+            ) else {
+                return;
+            };
+            stored_removed = shown - usize::from(!in_grid);
+        }
+        if !in_grid {
+            for command in &mut self.commands {
+                for point in command.points_mut() {
+                    if point.line < start {
+                        point.line += stored_removed;
+                    } else if point.line < end.line {
+                        point.line = end.line - 1;
+                    }
+                }
+            }
+            self.commands[index].folded = true;
+            return;
+        }
+
+        // Move everything above the output down onto it, the summary takes
+        // the place of its last line.
+        let grid_start = start.max(first_grid_line);
+        let first = Line((grid_start as i64 - self.scrolled as i64) as i32);
+        let lines = end.line - grid_start;
+        let removed = lines - 1;
+        let grid = term.grid_mut();
+        let rows = (0..lines).map(|line| grid[first + line].clone()).collect();
+        for line in (grid.topmost_line().0..first.0).rev().map(Line) {
+            grid[line + removed] = grid[line].clone();
+        }
+        grid[first + removed] = summary;
+
+        // Drop the duplicated lines at the top. Without enough history they
+        // are blanked on the screen instead.
+        let history = grid.history_size();
+        let dropped = removed.min(history);
+        grid.update_history(history - dropped);
+        grid.update_history(self.grid_limit());
+        for line in 0..removed - dropped {
+            grid[Line(line as i32)].reset(&Cell::default());
+        }
+
+        for command in &mut self.commands {
+            for point in command.points_mut() {
+                if point.line < start.min(first_grid_line) {
+                    point.line += stored_removed + dropped;
+                } else if point.line < start {
+                    point.line += removed;
+                } else if point.line < end.line {
+                    point.line = end.line - 1;
+                }
+            }
+        }
+        let record = &mut self.commands[index];
+        record.fold = Some(GridFold {
+            rows,
+            // Lines above the output would sit between them otherwise.
+            blanked: if grid_start == first_grid_line {
+                removed - dropped
+            } else {
+                0
+            },
+        });
+        record.folded = true;
+        self.history = term.grid().history_size();
+    }
+
+    fn unfold<T>(&mut self, index: usize, term: &mut Term<T>) {
+        let record = &mut self.commands[index];
+        let (id, Some(output)) = (record.id, record.output) else {
+            return;
+        };
+        let unfolded = match record.fold.take() {
+            Some(fold) => match self.grid_point(term, output) {
+                Some(summary) => {
+                    self.unfold_rows(summary.line, fold, term);
+                    // Trimming the history may have pruned commands.
+                    if let Some(index) = self.index(id) {
+                        self.unfold_stored(index);
+                    }
+                    true
+                },
+                None => self.unfold_summary(index, fold),
+            },
+            None => self.unfold_stored(index),
+        };
+        if let Some(index) = self.index(id).filter(|_| unfolded) {
+            self.commands[index].folded = false;
+        }
+    }
+
+    fn index(&self, id: u64) -> Option<usize> {
+        self.commands.iter().position(|command| command.id == id)
+    }
+
+    /// Puts output rows back into the grid in place of their summary.
+    fn unfold_rows<T>(
+        &mut self,
+        summary: Line,
+        fold: GridFold,
+        term: &mut Term<T>,
+    ) {
+        let GridFold { mut rows, blanked } = fold;
+        let added = rows.len() - 1;
+        let columns = term.columns();
+        let grid = term.grid_mut();
+        // The blanked lines take rows again while they are in the grid.
+        let reused = if summary.0 - blanked as i32 >= grid.topmost_line().0 {
+            blanked.min(added)
+        } else {
+            0
+        };
+
+        // Scroll everything up to make room, then move the lines below the
+        // summary back down.
+        let scrolled = added - reused;
+        let screen_lines = grid.screen_lines();
+        let history = grid.history_size();
+        grid.update_history(self.grid_limit().max(history + scrolled));
+        let mut remaining = scrolled;
+        while remaining > 0 {
+            let positions = remaining.min(screen_lines);
+            grid.scroll_up(&(Line(0)..Line(screen_lines as i32)), positions);
+            remaining -= positions;
+        }
+        let bottommost = grid.bottommost_line();
+        for line in (summary.0 + 1..=bottommost.0).rev().map(Line) {
+            grid[line] = grid[line - scrolled].clone();
+        }
+        for (line, row) in rows.iter_mut().enumerate() {
+            if row.len() < columns {
+                row.grow(columns);
+            } else {
+                row.shrink(columns);
+            }
+            grid[summary - added + line] = row.clone();
+        }
+
+        let summary_line = (self.scrolled as i64 + summary.0 as i64) as usize;
+        for command in &mut self.commands {
+            for point in command.points_mut() {
+                if point.line < summary_line {
+                    point.line -= scrolled;
+                } else if point.line == summary_line {
+                    point.line -= added;
+                }
+            }
+        }
+        self.history = term.grid().history_size();
+        // Lines the fold blanked on the screen are history now as well.
+        if self.history > self.scrolled {
+            let blanked = self.history - self.scrolled;
+            self.scrolled += blanked;
+            for command in &mut self.commands {
+                for point in command.points_mut() {
+                    point.line += blanked;
+                }
+            }
+        }
+        // Trims the history again if it grew past its limit.
+        self.update(term);
+    }
+
+    /// Shows the output of the command at `index` folded or hidden in the
+    /// overflow store again.
+    fn unfold_stored(&mut self, index: usize) -> bool {
+        let Some(overflow) = &self.overflow else {
+            return false;
+        };
+        let oldest = self.oldest_line();
+        let mut overflow = overflow.lock().unwrap();
+        let len = overflow.len();
+        let Some((at, _)) = overflow.unfold(self.commands[index].id) else {
+            return false;
+        };
+        let added = overflow.len() - len;
+        drop(overflow);
+
+        self.move_unfolded(index, oldest + at, added);
+        true
+    }
+
+    /// Unfolds the output of the command at `index` whose summary was
+    /// spilled into the overflow store, keeping it folded if it was
+    /// dropped instead.
+    fn unfold_summary(&mut self, index: usize, fold: GridFold) -> bool {
+        let oldest = self.oldest_line();
+        let record = &mut self.commands[index];
+        let (Some(overflow), Some(output)) = (&self.overflow, record.output)
+        else {
+            record.fold = Some(fold);
+            return false;
+        };
+        let mut overflow = overflow.lock().unwrap();
+        let len = overflow.len();
+        let cells = fold.rows.iter().map(row_cells).collect();
+        let unfolded = output.line.checked_sub(oldest).and_then(|summary| {
+            overflow.unfold_summary(record.id, summary, fold.blanked, cells)
+        });
+        let Some(at) = unfolded else {
+            record.fold = Some(fold);
+            return false;
+        };
+        let added = overflow.len() - len;
+        drop(overflow);
+
+        self.move_unfolded(index, oldest + at, added);
+        true
+    }
+
+    /// Moves the marks above `line` up for `added` lines of the command at
+    /// `index` unfolded there, and its output to their start.
+    fn move_unfolded(&mut self, index: usize, line: usize, added: usize) {
+        for command in &mut self.commands {
+            for point in command.points_mut() {
+                if point.line < line {
+                    point.line -= added;
+                }
+            }
+        }
+        if let Some(output) = &mut self.commands[index].output {
+            output.line = line - added;
+        }
+    }
+
+    /// History the grid keeps before lines are dropped or spilled.
+    fn grid_limit(&self) -> usize {
+        match &self.overflow {
+            Some(overflow) => overflow.lock().unwrap().grid_history(),
+            None => self.hot_history + TRACKING_MARGIN,
+        }
+    }
+
+    /// Absolute line of the oldest line still stored anywhere.
+    fn oldest_line(&self) -> usize {
+        let stored = self
+            .overflow
+            .as_ref()
+            .map_or(0, |overflow| overflow.lock().unwrap().len());
+        self.scrolled.saturating_sub(self.history + stored)
+    }
+
+    fn prune(&mut self) {
+        let oldest = self.oldest_line();
+        while self
+            .commands
+            .front()
+            .is_some_and(|command| command.prompt.line < oldest)
+        {
+            self.commands.pop_front();
+        }
+    }
+
+    /// Absolute line shown at the top of the viewport.
+    pub(crate) fn top_line<T>(
+        &self,
+        term: &Term<T>,
+        overflow_offset: usize,
+    ) -> usize {
+        self.scrolled
+            .saturating_sub(term.grid().display_offset() + overflow_offset)
+    }
+
+    /// Grid point of an absolute position, `None` once it left the grid.
+    pub(crate) fn grid_point<T>(
+        &self,
+        term: &Term<T>,
+        point: MarkPoint,
+    ) -> Option<Point> {
+        let line = point.line as i64 - self.scrolled as i64;
+        let topmost = term.grid().topmost_line().0 as i64;
+        let bottommost = term.grid().bottommost_line().0 as i64;
+        (topmost..=bottommost)
+            .contains(&line)
+            .then(|| Point::new(Line(line as i32), point.column))
+    }
+
+    /// Index in the overflow store of an absolute line.
+    pub(crate) fn overflow_index(&self, line: usize) -> Option<usize> {
+        let oldest = self.oldest_line();
+        let first_grid_line = self.scrolled.saturating_sub(self.history);
+        (oldest..first_grid_line)
+            .contains(&line)
+            .then(|| line - oldest)
+    }
+
+    pub(crate) fn previous_prompt(&self, top_line: usize) -> Option<usize> {
+        self.commands
+            .iter()
+            .rev()
+            .map(|command| command.prompt.line)
+            .find(|&line| line < top_line)
+    }
+
+    pub(crate) fn next_prompt(&self, top_line: usize) -> Option<usize> {
+        self.commands
+            .iter()
+            .map(|command| command.prompt.line)
+            .find(|&line| line > top_line)
+    }
+
+    /// Output range of the most recent finished command.
+    pub(crate) fn last_output(&self) -> Option<(MarkPoint, MarkPoint)> {
+        self.commands
+            .iter()
+            .rev()
+            .find_map(|command| Some((command.output?, command.end?)))
+    }
+
+    /// Output range of the command `id`, once it finished.
+    pub(crate) fn output(&self, id: u64) -> Option<(MarkPoint, MarkPoint)> {
+        let command = self.commands.iter().find(|command| command.id == id)?;
+        Some((command.output?, command.end?))
+    }
+
+    /// Commands intersecting the `screen_lines` lines from `top_line`.
+    pub(crate) fn visible(
+        &self,
+        top_line: usize,
+        screen_lines: usize,
+    ) -> Vec<CommandInfo> {
+        let bottom_line = top_line + screen_lines;
+        let relative = |line: usize| line as i32 - top_line as i32;
+        self.commands
+            .iter()
+            .filter(|command| {
+                command.prompt.line < bottom_line
+                    && command.end.is_none_or(|end| end.line >= top_line)
+            })
+            .map(|command| CommandInfo {
+                id: command.id,
+                prompt_line: relative(command.prompt.line),
+                output_line: command.output.map(|point| relative(point.line)),
+                end_line: command.end.map(|point| relative(point.line)),
+                state: command.state(),
+                duration: command.duration(),
+                folded: command.folded,
+            })
+            .collect()
+    }
+}
+
+/// Cells of a grid row, as the overflow store keeps them.
+fn row_cells(row: &Row<Cell>) -> Vec<Cell> {
+    (0..row.len())
+        .map(|column| row[Column(column)].clone())
+        .collect()
+}
+
+/// `48213` as `48,213`.
+fn group_thousands(number: usize) -> String {
+    let digits = number.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_mark, MarkScanner, ShellMark};
+    use std::path::PathBuf;
+
+    fn scan_all(
+        scanner: &mut MarkScanner,
+        bytes: &[u8],
+    ) -> Vec<(usize, Option<ShellMark>)> {
+        let mut found = Vec::new();
+        let mut offset = 0;
+        while let Some((end, mark)) = scanner.scan(&bytes[offset..]) {
+            offset += end;
+            found.push((offset, mark));
+        }
+        found
+    }
+
+    #[test]
+    fn scanner_finds_marks_across_chunks() {
+        let mut scanner = MarkScanner::default();
+        assert_eq!(scan_all(&mut scanner, b"$ \x1b]133;D;12"), []);
+        assert_eq!(
+            scan_all(&mut scanner, b"7\x1b\\ls\x1b]133;B\x07\x1b]0;title\x07"),
+            [
+                (3, Some(ShellMark::CommandFinished(Some(127)))),
+                (13, Some(ShellMark::CommandStart)),
+            ]
+        );
+        assert_eq!(
+            scan_all(&mut scanner, b"\x1b[1m\x1b[?1049hx\x1b]133;A;aid=1\x07"),
+            [(12, None), (27, Some(ShellMark::PromptStart))]
+        );
+    }
+
+    #[test]
+    fn backend_tracks_commands_across_scrolling() {
+        use crate::backend::source::ChannelSource;
+        use crate::{
+            BackendCommand, BackendSettings, CommandState, HeadlessTerminal,
+        };
+        use std::time::Duration;
+
+        let (source, peer) = ChannelSource::new();
+        let mut terminal = HeadlessTerminal::with_source(
+            Box::new(source),
+            BackendSettings::default(),
+            20,
+            4,
+        )
+        .unwrap();
+        let prompt = |command: &str, output: &str, code: i32| {
+            format!(
+                "\x1b]133;A\x07$ \x1b]133;B\x07{command}\r\n\x1b]133;C\x07\
+                 {output}\x1b]133;D;{code}\x07"
+            )
+        };
+        let mut stream = prompt("true", "", 0);
+        stream += &prompt("seq 3", "1\r\n2\r\n3\r\n", 1);
+        stream += "\x1b]133;A\x07$ \x1b]133;B\x07";
+        peer.output.send(stream.into_bytes()).unwrap();
+
+        let backend = terminal.backend_mut();
+        backend
+            .wait_for_regex(r"3\n\$", Duration::from_secs(5))
+            .unwrap();
+        let content = backend.sync();
+        // The first command scrolled off, the second one is partly visible.
+        assert_eq!(content.commands.len(), 2);
+        assert_eq!(content.commands[0].prompt_line, -1);
+        assert_eq!(content.commands[0].state, CommandState::Finished(Some(1)));
+        assert_eq!(content.commands[1].state, CommandState::Editing);
+
+        backend.process_command(BackendCommand::SelectLastCommandOutput);
+        let selected = backend.selectable_content();
+        let lines: Vec<_> = selected.lines().map(str::trim_end).collect();
+        assert_eq!(lines, ["1", "2", "3"]);
+
+        backend.process_command(BackendCommand::PreviousPrompt);
+        assert_eq!(backend.sync().display_offset, 1);
+        backend.process_command(BackendCommand::PreviousPrompt);
+        assert_eq!(backend.sync().display_offset, 2);
+        backend.process_command(BackendCommand::NextPrompt);
+        assert_eq!(backend.sync().display_offset, 1);
+    }
+
+    #[test]
+    fn folds_command_output_into_a_summary() {
+        use crate::backend::source::ChannelSource;
+        use crate::{BackendCommand, BackendSettings, HeadlessTerminal};
+        use std::time::Duration;
+
+        let (source, peer) = ChannelSource::new();
+        let mut terminal = HeadlessTerminal::with_source(
+            Box::new(source),
+            BackendSettings::default(),
+            30,
+            4,
+        )
+        .unwrap();
+        let output: String = (1..=10).map(|n| format!("{n}\r\n")).collect();
+        let stream = format!(
+            "\x1b]133;A\x07$ \x1b]133;B\x07seq 10\r\n\x1b]133;C\x07{output}\
+             \x1b]133;D;0\x07\x1b]133;A\x07$ \x1b]133;B\x07"
+        );
+        peer.output.send(stream.into_bytes()).unwrap();
+
+        let backend = terminal.backend_mut();
+        backend
+            .wait_for_regex(r"10\n\$", Duration::from_secs(5))
+            .unwrap();
+        let unfolded = backend.screen_text();
+
+        backend.process_command(BackendCommand::ToggleLastFold);
+        assert_eq!(
+            backend.screen_text(),
+            "\n$ seq 10\n▶ seq 10 — 10 lines, exit 0\n$"
+        );
+        let content = backend.sync();
+        assert!(content.commands[0].folded);
+        assert_eq!(content.commands[0].prompt_line, 1);
+        assert_eq!(content.commands[1].prompt_line, 3);
+        backend.search_set_active(true);
+        backend.search_set_query("5");
+        assert_eq!(backend.search_next(), None);
+
+        backend.process_command(BackendCommand::ToggleLastFold);
+        assert_eq!(backend.screen_text(), unfolded);
+        let content = backend.sync();
+        assert!(!content.commands[0].folded);
+        assert_eq!(content.commands[0].prompt_line, -8);
+        assert_eq!(content.commands[0].output_line, Some(-7));
+        assert!(backend.search_next().is_some());
+        backend.search_set_active(false);
+
+        backend.process_command(BackendCommand::PreviousPrompt);
+        assert_eq!(backend.screen_text(), "$ seq 10\n1\n2\n3");
+    }
+
+    #[test]
+    fn folds_output_larger_than_the_history() {
+        use crate::backend::settings::{OverflowSettings, TerminalConfig};
+        use crate::backend::source::ChannelSource;
+        use crate::{
+            BackendCommand, BackendSettings, ExportFormat, ExportRange,
+            HeadlessTerminal,
+        };
+        use std::time::Duration;
+
+        let (source, peer) = ChannelSource::new();
+        let mut terminal = HeadlessTerminal::with_source(
+            Box::new(source),
+            BackendSettings {
+                terminal_config: TerminalConfig {
+                    scrolling_history: 50,
+                    ..TerminalConfig::default()
+                },
+                overflow: Some(OverflowSettings::default()),
+                ..BackendSettings::default()
+            },
+            40,
+            4,
+        )
+        .unwrap();
+        let output: String = (1..=5000).map(|n| format!("{n}\r\n")).collect();
+        let stream = format!(
+            "\x1b]133;A\x07$ \x1b]133;B\x07seq 5000\r\n\x1b]133;C\x07{output}\
+             \x1b]133;D;0\x07\x1b]133;A\x07$ \x1b]133;B\x07"
+        );
+        peer.output.send(stream.into_bytes()).unwrap();
+        let timeout = Duration::from_secs(5);
+        terminal
+            .backend_mut()
+            .wait_for_regex(r"5000\n\$", timeout)
+            .unwrap();
+
+        let scrollback = |terminal: &HeadlessTerminal| {
+            let text = terminal
+                .backend()
+                .export(ExportRange::Scrollback, &ExportFormat::PlainText);
+            text.lines()
+                .map(str::trim_end)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        let unfolded = scrollback(&terminal);
+        assert_eq!(unfolded.len(), 5002);
+        let folded = ["$ seq 5000", "▶ seq 5000 — 5,000 lines, exit 0", "$"];
+        let toggle = |terminal: &mut HeadlessTerminal| {
+            let backend = terminal.backend_mut();
+            backend.process_command(BackendCommand::ToggleLastFold);
+            backend.sync();
+        };
+
+        toggle(&mut terminal);
+        assert_eq!(scrollback(&terminal), folded);
+        assert!(terminal.backend_mut().sync().commands[0].folded);
+        let backend = terminal.backend_mut();
+        backend.search_set_active(true);
+        backend.search_set_query("^2500$");
+        assert_eq!(backend.search_next(), None);
+        backend.search_set_active(false);
+        toggle(&mut terminal);
+        assert_eq!(scrollback(&terminal), unfolded);
+
+        // Folds survive resizing.
+        toggle(&mut terminal);
+        terminal.resize(36, 4);
+        assert_eq!(scrollback(&terminal), folded);
+
+        // The summary follows the output into the store and unfolds there.
+        let more: String = (0..200).map(|n| format!("\r\nmore {n}")).collect();
+        peer.output.send(more.into_bytes()).unwrap();
+        terminal
+            .backend_mut()
+            .wait_for_regex("more 199", timeout)
+            .unwrap();
+        toggle(&mut terminal);
+        let lines = scrollback(&terminal);
+        assert_eq!(lines[..5002], unfolded[..]);
+        assert_eq!(lines.len(), 5202);
+        toggle(&mut terminal);
+        assert_eq!(scrollback(&terminal)[..3], folded[..]);
+        toggle(&mut terminal);
+        assert_eq!(scrollback(&terminal), lines);
+    }
+
+    #[test]
+    fn parses_working_directory_reports() {
+        let dir = |url: &str| match parse_mark(format!("7;{url}").as_bytes()) {
+            Some(ShellMark::CurrentDir(dir)) => dir,
+            mark => panic!("unexpected {mark:?}"),
+        };
+        assert_eq!(dir("file:///tmp/a%20b"), Some(PathBuf::from("/tmp/a b")));
+        assert_eq!(dir("file://localhost/srv"), Some(PathBuf::from("/srv")));
+        assert_eq!(dir("file://elsewhere.example/home"), None);
+        assert_eq!(parse_mark(b"7;http://host/path"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reports_and_inherits_the_working_directory() {
+        use crate::{BackendSettings, HeadlessTerminal, Waker};
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let settings = BackendSettings {
+            shell: "/bin/sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo ready; read line; printf '\\033]7;file://%s%s\\007' \
+                 \"$(hostname)\" /; echo moved; read line"
+                    .to_string(),
+            ],
+            working_directory: Some(PathBuf::from("/tmp")),
+            ..BackendSettings::default()
+        };
+        let mut terminal = HeadlessTerminal::new(settings, 30, 5).unwrap();
+        let timeout = Duration::from_secs(5);
+        terminal.backend().wait_for_regex("ready", timeout).unwrap();
+        // Read from /proc until the shell reports a directory.
+        assert_eq!(terminal.backend().current_dir(), Some("/tmp".into()));
+
+        terminal.write("\r");
+        terminal.backend().wait_for_regex("moved", timeout).unwrap();
+        assert_eq!(terminal.backend().current_dir(), Some("/".into()));
+
+        let (sender, _events) = mpsc::channel();
+        let sibling = terminal
+            .backend()
+            .new_in_current_dir(1, Waker::default(), sender)
+            .unwrap();
+        sibling.wait_for_regex("ready", timeout).unwrap();
+        assert_eq!(sibling.current_dir(), Some("/".into()));
+    }
+}
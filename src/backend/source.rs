@@ -0,0 +1,1344 @@
+//! Byte streams a [`TerminalBackend`](super::TerminalBackend) can drive.
+//!
+//! A source hands out a blocking reader and writer and gets told about
+//! resizes and shutdown. The backend runs three threads per source: one
+//! blocks on the reader, one feeds the output into the terminal parser and
+//! one owns the source and writes input to it.
+
+use alacritty_terminal::event::{Event, EventListener, WindowSize};
+use alacritty_terminal::sync::FairMutex;
+use alacritty_terminal::term::Term;
+use alacritty_terminal::tty;
+use alacritty_terminal::vte::ansi::Processor;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Result, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use super::process::ExitStatus;
+use super::recorder::{record, SharedRecorder};
+use super::settings::{BackendSettings, StartupInput, StartupReady};
+use super::shell_integration::{
+    MarkScanner, SharedCommandTracker, ShellMark, TRACKING_MARGIN,
+};
+use super::{EventProxy, PtyEvent};
+
+#[cfg(windows)]
+use alacritty_terminal::event::OnResize;
+#[cfg(windows)]
+use alacritty_terminal::tty::{ChildEvent, EventedPty, EventedReadWrite};
+#[cfg(windows)]
+use polling::{Events, PollMode, Poller};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::time::Duration;
+
+/// Size of a single read from a source.
+const READ_BUFFER_SIZE: usize = 0x1_0000;
+
+/// Bytes parsed before the terminal lock is released for the renderer.
+const MAX_LOCKED_READ: usize = u16::MAX as usize;
+
+/// How long to wait for an exit status once the output stream closed.
+#[cfg(unix)]
+const EXIT_STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Transport between the terminal and whatever produces its output.
+///
+/// [`take_reader`](Self::take_reader) and [`take_writer`](Self::take_writer)
+/// are called once each when the backend starts. The reader reaching EOF
+/// or failing ends the session.
+pub trait BackendSource: Send {
+    /// Blocking stream of terminal output.
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>>;
+
+    /// Blocking stream for keyboard input and terminal replies.
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>>;
+
+    /// Called when the terminal grid changes size.
+    fn resize(&mut self, _window_size: WindowSize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Process behind the source, if any.
+    fn process_id(&self) -> Option<u32> {
+        None
+    }
+
+    /// Another handle to the terminal device, used to ask which process
+    /// group is in the foreground. Only sources backed by a tty have one.
+    fn terminal_device(&self) -> Option<File> {
+        None
+    }
+
+    /// How the process behind the source ended. Called after the reader
+    /// closed.
+    fn wait(&mut self) -> Option<ExitStatus> {
+        None
+    }
+
+    /// Releases the source when the backend is dropped or the session
+    /// ended. A read still blocked on the reader should return.
+    fn shutdown(&mut self) {}
+}
+
+fn already_taken(stream: &str) -> io::Error {
+    io::Error::other(format!("source {stream} was already taken"))
+}
+
+/// The shell of a backend could not be started, see [`BackendError`].
+#[derive(Debug)]
+pub struct SpawnError {
+    /// Program that was started.
+    pub shell: String,
+    pub error: io::Error,
+}
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shell = &self.shell;
+        match self.error.kind() {
+            io::ErrorKind::NotFound => write!(f, "shell {shell:?} not found"),
+            io::ErrorKind::PermissionDenied => {
+                write!(f, "shell {shell:?} is not executable")
+            },
+            _ => write!(f, "cannot start shell {shell:?}: {}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Why [`TerminalBackend::new`](super::TerminalBackend::new) or
+/// [`respawn`](super::TerminalBackend::respawn) failed.
+#[derive(Debug)]
+pub enum BackendError {
+    /// The shell could not be started.
+    Spawn(SpawnError),
+    /// The backend itself could not be set up or restarted.
+    Io(io::Error),
+}
+
+impl BackendError {
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::Spawn(err) => err.error.kind(),
+            Self::Io(err) => err.kind(),
+        }
+    }
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spawn(err) => err.fmt(f),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Spawn(err) => err.source(),
+            Self::Io(err) => err.source(),
+        }
+    }
+}
+
+impl From<io::Error> for BackendError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<SpawnError> for BackendError {
+    fn from(err: SpawnError) -> Self {
+        Self::Spawn(err)
+    }
+}
+
+impl From<BackendError> for io::Error {
+    fn from(err: BackendError) -> Self {
+        match err {
+            BackendError::Spawn(err) => io::Error::new(err.error.kind(), err),
+            BackendError::Io(err) => err,
+        }
+    }
+}
+
+/// A shell or program attached to a pseudo terminal.
+pub struct PtySource {
+    #[cfg(unix)]
+    master: File,
+    #[cfg(unix)]
+    pid: u32,
+    /// Owns the child if alacritty spawned it.
+    #[cfg(unix)]
+    _pty: Option<tty::Pty>,
+    /// Dropped to interrupt the reader.
+    #[cfg(unix)]
+    interrupt: Option<io::PipeWriter>,
+    #[cfg(windows)]
+    pty: Arc<Mutex<tty::Pty>>,
+    #[cfg(windows)]
+    state: Arc<ConptyState>,
+}
+
+impl PtySource {
+    pub fn spawn(
+        options: &tty::Options,
+        window_size: WindowSize,
+        id: u64,
+    ) -> Result<Self> {
+        let pty = tty::new(options, window_size, id)?;
+        #[cfg(unix)]
+        return Ok(Self {
+            master: pty.file().try_clone()?,
+            pid: pty.child().id(),
+            _pty: Some(pty),
+            interrupt: None,
+        });
+        #[cfg(windows)]
+        return ConptyState::register(pty);
+    }
+}
+
+#[cfg(unix)]
+impl PtySource {
+    /// Runs `command` on a new pty, as the leader of a new session with
+    /// the pty as its controlling terminal. Unlike [`Self::spawn`] the
+    /// command's argv\[0\] and environment are used as they are.
+    pub fn spawn_command(
+        mut command: Command,
+        window_size: WindowSize,
+    ) -> Result<Self> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+        use std::os::unix::process::CommandExt;
+
+        let winsize = winsize(window_size);
+        let (mut master, mut slave) = (0, 0);
+        let res = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &winsize,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) = unsafe {
+            (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+        };
+        for fd in [&master, &slave] {
+            if unsafe {
+                libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC)
+            } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(master.as_raw_fd(), &mut termios) == 0 {
+                termios.c_iflag |= libc::IUTF8;
+                libc::tcsetattr(master.as_raw_fd(), libc::TCSANOW, &termios);
+            }
+        }
+
+        command
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+        unsafe {
+            command.pre_exec(|| {
+                // stdin is the pty by now.
+                if libc::setsid() < 0
+                    || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                for signal in [
+                    libc::SIGCHLD,
+                    libc::SIGHUP,
+                    libc::SIGINT,
+                    libc::SIGQUIT,
+                    libc::SIGTERM,
+                    libc::SIGALRM,
+                    libc::SIGPIPE,
+                ] {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // The command keeps copies of the client side open, the reader
+        // would never see the shell hang up.
+        drop(command);
+
+        Ok(Self {
+            master: File::from(master),
+            pid: child.id(),
+            _pty: None,
+            interrupt: None,
+        })
+    }
+
+    /// Duplicate of the master fd in blocking mode for the writer.
+    /// `tty::new` opens it non-blocking for polling.
+    fn blocking_master(&self) -> Result<File> {
+        use std::os::fd::AsRawFd;
+
+        let file = self.master.try_clone()?;
+        let fd = file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0
+                || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(unix)]
+impl BackendSource for PtySource {
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        let (reader, interrupt) =
+            InterruptibleReader::new(self.master.try_clone()?)?;
+        self.interrupt = Some(interrupt);
+        Ok(Box::new(reader))
+    }
+
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
+        Ok(Box::new(self.blocking_master()?))
+    }
+
+    fn resize(&mut self, window_size: WindowSize) -> Result<()> {
+        set_window_size(&self.master, window_size)
+    }
+
+    fn process_id(&self) -> Option<u32> {
+        Some(self.pid)
+    }
+
+    fn terminal_device(&self) -> Option<File> {
+        self.master.try_clone().ok()
+    }
+
+    fn wait(&mut self) -> Option<ExitStatus> {
+        // Reading fails with EIO as soon as the last client fd closed, the
+        // shell may not have exited yet. It is reaped here rather than with
+        // `next_child_event`, which drops the terminating signal.
+        let pid = self.pid as libc::pid_t;
+        let deadline = Instant::now() + EXIT_STATUS_TIMEOUT;
+        while Instant::now() < deadline {
+            let mut status = 0;
+            match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+                0 => std::thread::sleep(Duration::from_millis(10)),
+                reaped if reaped == pid => {
+                    return Some(ExitStatus::from_wait_status(status));
+                },
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn shutdown(&mut self) {
+        self.interrupt = None;
+    }
+}
+
+/// Reader of a terminal device or pipe that stops when the source is shut
+/// down. A process still holding the other side, such as a job started
+/// with `nohup`, would keep a plain read blocked forever.
+#[cfg(unix)]
+struct InterruptibleReader {
+    file: File,
+    /// Hangs up once the write end is dropped.
+    interrupted: io::PipeReader,
+}
+
+#[cfg(unix)]
+impl InterruptibleReader {
+    /// Reader of `file` and the pipe end that interrupts it when dropped.
+    fn new(file: File) -> Result<(Self, io::PipeWriter)> {
+        let (interrupted, interrupt) = io::pipe()?;
+        Ok((Self { file, interrupted }, interrupt))
+    }
+}
+
+#[cfg(unix)]
+impl Read for InterruptibleReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        loop {
+            let mut fds = [self.file.as_raw_fd(), self.interrupted.as_raw_fd()]
+                .map(|fd| libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                });
+            if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if fds[1].revents != 0 {
+                return Ok(0);
+            }
+            // The file may be non-blocking and polled ready spuriously.
+            match self.file.read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {},
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Poller keys of the ConPTY pipes and the child exit.
+#[cfg(windows)]
+const CONPTY_PIPE_KEY: usize = 0;
+#[cfg(windows)]
+const CONPTY_CHILD_KEY: usize = 1;
+
+/// ConPTY pipes never block. Alacritty drains them on threads of its own
+/// and posts to a poller whenever a pipe that came up empty has data, or
+/// one that was full has room again, so the reader and the writer each
+/// wait on a poller of their own with the pty unlocked.
+#[cfg(windows)]
+struct ConptyState {
+    reader: Arc<Poller>,
+    writer: Arc<Poller>,
+    /// Set once the child exited, to its exit code if there is one.
+    exit_code: Mutex<Option<Option<i32>>>,
+    stopped: AtomicBool,
+}
+
+#[cfg(windows)]
+impl ConptyState {
+    fn register(mut pty: tty::Pty) -> Result<PtySource> {
+        let state = ConptyState {
+            reader: Arc::new(Poller::new()?),
+            writer: Arc::new(Poller::new()?),
+            exit_code: Mutex::default(),
+            stopped: AtomicBool::new(false),
+        };
+        pty.reader().register(
+            &state.reader,
+            polling::Event::readable(CONPTY_PIPE_KEY),
+            PollMode::Level,
+        );
+        pty.writer().register(
+            &state.writer,
+            polling::Event::writable(CONPTY_PIPE_KEY),
+            PollMode::Level,
+        );
+        pty.child_watcher().register(
+            &state.reader,
+            polling::Event::readable(CONPTY_CHILD_KEY),
+        );
+        Ok(PtySource {
+            pty: Arc::new(Mutex::new(pty)),
+            state: Arc::new(state),
+        })
+    }
+
+    fn closed(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+            || self.exit_code.lock().unwrap().is_some()
+    }
+
+    /// Wakes both streams so they see the child exit or the shutdown.
+    fn notify(&self) {
+        let _ = self.reader.notify();
+        let _ = self.writer.notify();
+    }
+}
+
+#[cfg(windows)]
+struct ConptyReader {
+    pty: Arc<Mutex<tty::Pty>>,
+    state: Arc<ConptyState>,
+    events: Events,
+}
+
+#[cfg(windows)]
+impl Read for ConptyReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            {
+                let mut pty = self.pty.lock().unwrap();
+                let read = pty.reader().read(buf)?;
+                if read > 0 {
+                    return Ok(read);
+                }
+                if let Some(ChildEvent::Exited(code)) = pty.next_child_event() {
+                    *self.state.exit_code.lock().unwrap() = Some(code);
+                    self.state.notify();
+                }
+            }
+            if self.state.closed() {
+                return Ok(0);
+            }
+            self.events.clear();
+            self.state.reader.wait(&mut self.events, None)?;
+        }
+    }
+}
+
+#[cfg(windows)]
+struct ConptyWriter {
+    pty: Arc<Mutex<tty::Pty>>,
+    state: Arc<ConptyState>,
+    events: Events,
+}
+
+#[cfg(windows)]
+impl Write for ConptyWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        loop {
+            let written = self.pty.lock().unwrap().writer().write(buf)?;
+            if written > 0 || buf.is_empty() {
+                return Ok(written);
+            }
+            if self.state.closed() {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.events.clear();
+            self.state.writer.wait(&mut self.events, None)?;
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl BackendSource for PtySource {
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(ConptyReader {
+            pty: self.pty.clone(),
+            state: self.state.clone(),
+            events: Events::new(),
+        }))
+    }
+
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
+        Ok(Box::new(ConptyWriter {
+            pty: self.pty.clone(),
+            state: self.state.clone(),
+            events: Events::new(),
+        }))
+    }
+
+    fn resize(&mut self, window_size: WindowSize) -> Result<()> {
+        self.pty.lock().unwrap().on_resize(window_size);
+        Ok(())
+    }
+
+    fn process_id(&self) -> Option<u32> {
+        let pty = self.pty.lock().unwrap();
+        pty.child_watcher().pid().map(u32::from)
+    }
+
+    fn wait(&mut self) -> Option<ExitStatus> {
+        let code = self.state.exit_code.lock().unwrap().flatten()?;
+        Some(ExitStatus {
+            code: Some(code),
+            signal: None,
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.state.stopped.store(true, Ordering::Release);
+        self.state.notify();
+    }
+}
+
+/// A program whose stdin, stdout and stderr are plain pipes.
+///
+/// There is no line discipline, so output newlines are expanded to CR LF
+/// the way a terminal's `onlcr` would, and input is not echoed.
+pub struct PipeSource {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Option<io::PipeReader>,
+    /// Dropped to interrupt the reader.
+    #[cfg(unix)]
+    interrupt: Option<io::PipeWriter>,
+}
+
+impl PipeSource {
+    /// Spawns `command` with stdout and stderr merged into one stream.
+    pub fn spawn(mut command: Command) -> Result<Self> {
+        let (output, output_writer) = io::pipe()?;
+        command
+            .stdin(Stdio::piped())
+            .stdout(output_writer.try_clone()?)
+            .stderr(output_writer);
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let mut child = command.spawn()?;
+        // The command keeps the write ends open, the reader would never
+        // see EOF.
+        drop(command);
+        let stdin = child.stdin.take();
+        Ok(Self {
+            child,
+            stdin,
+            output: Some(output),
+            #[cfg(unix)]
+            interrupt: None,
+        })
+    }
+}
+
+impl BackendSource for PipeSource {
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        let output =
+            self.output.take().ok_or_else(|| already_taken("reader"))?;
+        #[cfg(unix)]
+        let output = {
+            let file = File::from(std::os::fd::OwnedFd::from(output));
+            let (reader, interrupt) = InterruptibleReader::new(file)?;
+            self.interrupt = Some(interrupt);
+            reader
+        };
+        Ok(Box::new(NewlineReader {
+            inner: output,
+            pending: Vec::new(),
+        }))
+    }
+
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
+        let stdin = self.stdin.take().ok_or_else(|| already_taken("writer"))?;
+        Ok(Box::new(stdin))
+    }
+
+    fn process_id(&self) -> Option<u32> {
+        Some(self.child.id())
+    }
+
+    fn wait(&mut self) -> Option<ExitStatus> {
+        self.child.wait().ok().map(ExitStatus::from)
+    }
+
+    /// Signals are left to the [`ShutdownPolicy`](crate::ShutdownPolicy),
+    /// which reaches the whole process group of the child. A child that
+    /// outlives it keeps running.
+    fn shutdown(&mut self) {
+        self.stdin = None;
+        #[cfg(unix)]
+        {
+            self.interrupt = None;
+        }
+        let _ = self.child.try_wait();
+    }
+}
+
+/// Expands `\n` to `\r\n`.
+struct NewlineReader<R> {
+    inner: R,
+    pending: Vec<u8>,
+}
+
+impl<R: Read> Read for NewlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pending.is_empty() {
+            let mut raw = [0; 4096];
+            let read = self.inner.read(&mut raw)?;
+            for &byte in &raw[..read] {
+                if byte == b'\n' {
+                    self.pending.push(b'\r');
+                }
+                self.pending.push(byte);
+            }
+        }
+
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+/// An existing terminal device, such as the client side of a pty created
+/// elsewhere or a serial port.
+pub struct DeviceSource {
+    file: File,
+    /// Dropped to interrupt the reader.
+    #[cfg(unix)]
+    interrupt: Option<io::PipeWriter>,
+}
+
+impl DeviceSource {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut options = File::options();
+        options.read(true).write(true);
+        // Opening a tty must not make it our controlling terminal.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(
+            &mut options,
+            libc::O_NOCTTY,
+        );
+        Ok(Self {
+            file: options.open(path)?,
+            #[cfg(unix)]
+            interrupt: None,
+        })
+    }
+}
+
+impl BackendSource for DeviceSource {
+    #[cfg(unix)]
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        let (reader, interrupt) =
+            InterruptibleReader::new(self.file.try_clone()?)?;
+        self.interrupt = Some(interrupt);
+        Ok(Box::new(reader))
+    }
+
+    #[cfg(not(unix))]
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(self.file.try_clone()?))
+    }
+
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
+        Ok(Box::new(self.file.try_clone()?))
+    }
+
+    fn terminal_device(&self) -> Option<File> {
+        self.file.try_clone().ok()
+    }
+
+    #[cfg(unix)]
+    fn resize(&mut self, window_size: WindowSize) -> Result<()> {
+        set_window_size(&self.file, window_size)
+    }
+
+    #[cfg(unix)]
+    fn shutdown(&mut self) {
+        self.interrupt = None;
+    }
+}
+
+#[cfg(unix)]
+fn winsize(window_size: WindowSize) -> libc::winsize {
+    libc::winsize {
+        ws_row: window_size.num_lines,
+        ws_col: window_size.num_cols,
+        ws_xpixel: window_size.num_cols.saturating_mul(window_size.cell_width),
+        ws_ypixel: window_size
+            .num_lines
+            .saturating_mul(window_size.cell_height),
+    }
+}
+
+#[cfg(unix)]
+fn set_window_size(tty: &File, window_size: WindowSize) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let winsize = winsize(window_size);
+    let res =
+        unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCSWINSZ, &winsize) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A connected unix domain socket, e.g. to a remote session multiplexer.
+#[cfg(unix)]
+pub struct UnixSocketSource {
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl UnixSocketSource {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_stream(UnixStream::connect(path)?))
+    }
+
+    pub fn from_stream(stream: UnixStream) -> Self {
+        Self { stream }
+    }
+}
+
+#[cfg(unix)]
+impl BackendSource for UnixSocketSource {
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(self.stream.try_clone()?))
+    }
+
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
+        Ok(Box::new(self.stream.try_clone()?))
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// In-process source fed through channels, see [`ChannelSource::new`].
+pub struct ChannelSource {
+    output: Option<Receiver<Vec<u8>>>,
+    input: Sender<Vec<u8>>,
+    resize: Sender<WindowSize>,
+}
+
+/// The other end of a [`ChannelSource`].
+pub struct ChannelPeer {
+    /// Bytes sent here are terminal output. Dropping it ends the session.
+    pub output: Sender<Vec<u8>>,
+    /// Keyboard input and terminal replies.
+    pub input: Receiver<Vec<u8>>,
+    /// New sizes of the terminal grid.
+    pub resize: Receiver<WindowSize>,
+}
+
+impl ChannelSource {
+    pub fn new() -> (Self, ChannelPeer) {
+        let (output, output_receiver) = mpsc::channel();
+        let (input_sender, input) = mpsc::channel();
+        let (resize_sender, resize) = mpsc::channel();
+        let source = Self {
+            output: Some(output_receiver),
+            input: input_sender,
+            resize: resize_sender,
+        };
+        (
+            source,
+            ChannelPeer {
+                output,
+                input,
+                resize,
+            },
+        )
+    }
+}
+
+impl BackendSource for ChannelSource {
+    fn take_reader(&mut self) -> Result<Box<dyn Read + Send>> {
+        let receiver =
+            self.output.take().ok_or_else(|| already_taken("reader"))?;
+        Ok(Box::new(ChannelReader {
+            receiver,
+            pending: Vec::new(),
+        }))
+    }
+
+    fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
+        Ok(Box::new(ChannelWriter(self.input.clone())))
+    }
+
+    fn resize(&mut self, window_size: WindowSize) -> Result<()> {
+        // The peer may not care about sizes.
+        let _ = self.resize.send(window_size);
+        Ok(())
+    }
+}
+
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.pending.is_empty() {
+            match self.receiver.recv() {
+                Ok(bytes) => self.pending = bytes,
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+enum Message {
+    Input(Cow<'static, [u8]>),
+    Resize(WindowSize),
+    /// The reader reached EOF.
+    Closed,
+    Shutdown,
+}
+
+/// Handle to the thread that owns a running source. Clones stay attached
+/// to the same terminal when a new session replaces the ended one.
+#[derive(Clone)]
+pub(crate) struct Notifier(Arc<Mutex<Session>>);
+
+#[derive(Clone)]
+struct Session {
+    sender: Sender<Message>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Notifier {
+    pub(crate) fn notify<B: Into<Cow<'static, [u8]>>>(&self, bytes: B) {
+        let bytes = bytes.into();
+        if !bytes.is_empty() {
+            self.send(Message::Input(bytes));
+        }
+    }
+
+    pub(crate) fn on_resize(&self, window_size: WindowSize) {
+        self.send(Message::Resize(window_size));
+    }
+
+    pub(crate) fn shutdown(&self) {
+        self.send(Message::Shutdown);
+    }
+
+    /// Whether the session ended and the source was released.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.0.lock().unwrap().closed.load(Ordering::Acquire)
+    }
+
+    /// Points this handle and all its clones at the session of `other`.
+    pub(crate) fn attach(&self, other: Notifier) {
+        let session = other.0.lock().unwrap().clone();
+        *self.0.lock().unwrap() = session;
+    }
+
+    fn send(&self, message: Message) {
+        let _ = self.0.lock().unwrap().sender.send(message);
+    }
+}
+
+/// Input held back until the shell is ready, see
+/// [`BackendSettings::startup_input`].
+pub(crate) struct Startup {
+    input: Vec<u8>,
+    ready: StartupReady,
+    deadline: Instant,
+}
+
+impl Startup {
+    pub(crate) fn new(settings: &BackendSettings) -> Option<Self> {
+        let mut input = Vec::new();
+        for startup_input in &settings.startup_input {
+            match startup_input {
+                StartupInput::Command(command) => {
+                    input.extend_from_slice(command.as_bytes());
+                    input.push(b'\r');
+                },
+                StartupInput::Raw(bytes) => input.extend_from_slice(bytes),
+            }
+        }
+        (!input.is_empty()).then(|| Self {
+            input,
+            ready: settings.startup_ready,
+            deadline: Instant::now() + settings.startup_timeout,
+        })
+    }
+
+    fn write(self, writer: &Sender<Message>, recorder: &SharedRecorder) {
+        record(recorder, |recorder| recorder.input(&self.input));
+        let _ = writer.send(Message::Input(self.input.into()));
+    }
+}
+
+/// Starts the threads that connect `source` to `term`. The session ends,
+/// with [`Event::Exit`], once the output closed or on
+/// [`Notifier::shutdown`].
+pub(crate) fn spawn(
+    id: u64,
+    mut source: Box<dyn BackendSource>,
+    term: Arc<FairMutex<Term<EventProxy>>>,
+    event_proxy: EventProxy,
+    recorder: SharedRecorder,
+    output_bytes: Arc<AtomicUsize>,
+    commands: SharedCommandTracker,
+    startup: Option<Startup>,
+) -> Result<(Notifier, Vec<JoinHandle<()>>)> {
+    let mut reader = source.take_reader()?;
+    let mut writer = source.take_writer()?;
+    let (sender, receiver) = mpsc::channel();
+    // Bounded, so a flood of output waits for the parser.
+    let (output_sender, output) = mpsc::sync_channel::<Vec<u8>>(16);
+
+    let mut threads = Vec::with_capacity(3);
+    let reader_thread = std::thread::Builder::new()
+        .name(format!("source_reader_{}", id))
+        .spawn(move || {
+            let mut buf = vec![0; READ_BUFFER_SIZE];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        if output_sender.send(buf[..read].to_vec()).is_err() {
+                            break;
+                        }
+                    },
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                    Err(err) => {
+                        // Linux reports EIO once the pty client hung up.
+                        #[cfg(target_os = "linux")]
+                        if err.raw_os_error() == Some(libc::EIO) {
+                            break;
+                        }
+                        eprintln!("source_reader_{}: read failed: {err}", id);
+                        break;
+                    },
+                }
+            }
+        })?;
+    threads.push(reader_thread);
+
+    let parser_term = term.clone();
+    let parser_proxy = event_proxy.clone();
+    let parser_writer = sender.clone();
+    let parser_thread = std::thread::Builder::new()
+        .name(format!("source_parser_{}", id))
+        .spawn(move || {
+            parse_output(
+                output,
+                parser_term,
+                parser_proxy,
+                recorder,
+                output_bytes,
+                commands,
+                &parser_writer,
+                startup,
+            );
+            let _ = parser_writer.send(Message::Closed);
+        })?;
+    threads.push(parser_thread);
+
+    let closed = Arc::new(AtomicBool::new(false));
+    let source_closed = closed.clone();
+    let writer_thread = std::thread::Builder::new()
+        .name(format!("source_writer_{}", id))
+        .spawn(move || {
+            while let Ok(message) = receiver.recv() {
+                match message {
+                    Message::Input(bytes) => {
+                        if let Err(err) = writer
+                            .write_all(&bytes)
+                            .and_then(|_| writer.flush())
+                        {
+                            eprintln!(
+                                "source_writer_{}: write failed: {err}",
+                                id
+                            );
+                        }
+                    },
+                    Message::Resize(window_size) => {
+                        if let Err(err) = source.resize(window_size) {
+                            eprintln!(
+                                "source_writer_{}: resize failed: {err}",
+                                id
+                            );
+                        }
+                    },
+                    Message::Closed => {
+                        if let Some(status) = source.wait() {
+                            event_proxy.send(PtyEvent::ChildExit(status));
+                        }
+                        break;
+                    },
+                    Message::Shutdown => break,
+                }
+            }
+            source.shutdown();
+            term.lock().exit();
+            event_proxy.send_event(Event::Wakeup);
+            // After `Exit` is queued, see `spawn_event_subscription`.
+            source_closed.store(true, Ordering::Release);
+        })?;
+    threads.push(writer_thread);
+
+    let session = Session { sender, closed };
+    Ok((Notifier(Arc::new(Mutex::new(session))), threads))
+}
+
+/// Feeds output chunks into the terminal until the reader closes, handling
+/// the timeout of synchronized updates and the startup input in between.
+#[allow(clippy::too_many_arguments)]
+fn parse_output(
+    output: Receiver<Vec<u8>>,
+    term: Arc<FairMutex<Term<EventProxy>>>,
+    event_proxy: EventProxy,
+    recorder: SharedRecorder,
+    output_bytes: Arc<AtomicUsize>,
+    commands: SharedCommandTracker,
+    writer: &Sender<Message>,
+    mut startup: Option<Startup>,
+) {
+    let mut parser: Processor = Processor::new();
+    let mut scanner = MarkScanner::default();
+    loop {
+        let sync_deadline = parser.sync_timeout().sync_timeout();
+        let startup_deadline = startup.as_ref().map(|startup| startup.deadline);
+        let chunk =
+            match sync_deadline.into_iter().chain(startup_deadline).min() {
+                Some(deadline) => {
+                    let timeout =
+                        deadline.saturating_duration_since(Instant::now());
+                    match output.recv_timeout(timeout) {
+                        Ok(chunk) => chunk,
+                        Err(RecvTimeoutError::Timeout) => {
+                            let now = Instant::now();
+                            if sync_deadline.is_some_and(|sync| sync <= now) {
+                                parser.stop_sync(&mut *term.lock());
+                                event_proxy.send_event(Event::Wakeup);
+                            }
+                            if let Some(startup) = startup
+                                .take_if(|startup| startup.deadline <= now)
+                            {
+                                startup.write(writer, &recorder);
+                            }
+                            continue;
+                        },
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                },
+                None => match output.recv() {
+                    Ok(chunk) => chunk,
+                    Err(_) => break,
+                },
+            };
+
+        let mut processed = 0;
+        let mut prompted = false;
+        let mut terminal = term.lock();
+        let mut next = Some(chunk);
+        while let Some(chunk) = next.take() {
+            record(&recorder, |recorder| recorder.output(&chunk));
+            // Stop at shell marks to record them at the cursor, and often
+            // enough to count every line that scrolls into the history.
+            let mut offset = 0;
+            while offset < chunk.len() {
+                let piece = (offset + TRACKING_MARGIN).min(chunk.len());
+                let (end, mark) = match scanner.scan(&chunk[offset..piece]) {
+                    Some((length, mark)) => (offset + length, mark),
+                    None => (piece, None),
+                };
+                parser.advance(&mut *terminal, &chunk[offset..end]);
+                let mut commands = commands.lock().unwrap();
+                commands.update(&mut terminal);
+                if let Some(mark) = mark {
+                    prompted |= mark == ShellMark::PromptStart;
+                    commands.mark(mark, &terminal);
+                }
+                offset = end;
+            }
+            processed += chunk.len();
+            if processed < MAX_LOCKED_READ {
+                next = output.try_recv().ok();
+            }
+        }
+        drop(terminal);
+        output_bytes.fetch_add(processed, Ordering::Relaxed);
+        if let Some(startup) = startup.take_if(|startup| {
+            prompted || startup.ready == StartupReady::Output
+        }) {
+            startup.write(writer, &recorder);
+        }
+
+        // Redraw unless everything went into a synchronized update.
+        if parser.sync_bytes_count() < processed {
+            event_proxy.send_event(Event::Wakeup);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendSource, ChannelSource, NewlineReader, PipeSource};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn channel_source_connects_both_directions() {
+        let (mut source, peer) = ChannelSource::new();
+        let mut reader = source.take_reader().unwrap();
+        let mut writer = source.take_writer().unwrap();
+        assert!(source.take_reader().is_err());
+
+        peer.output.send(b"hello".to_vec()).unwrap();
+        drop(peer.output);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "hello");
+
+        writer.write_all(b"ls\r").unwrap();
+        assert_eq!(peer.input.recv().unwrap(), b"ls\r");
+    }
+
+    #[test]
+    fn newline_reader_expands_line_feeds() {
+        let mut reader = NewlineReader {
+            inner: &b"a\nb\n"[..],
+            pending: Vec::new(),
+        };
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"a\r\nb\r\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipe_source_reports_exit_code() {
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let mut source = PipeSource::spawn(command).unwrap();
+        let mut reader = source.take_reader().unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "out\r\nerr\r\n");
+        assert_eq!(source.wait().and_then(|status| status.code), Some(3));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty_reader_stops_on_shutdown() {
+        use super::PtySource;
+        use alacritty_terminal::event::WindowSize;
+        use std::time::Duration;
+
+        // Keeps the pty open like a job that ignores the hangup.
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "echo started; exec sleep 30"]);
+        let window_size = WindowSize {
+            num_lines: 5,
+            num_cols: 20,
+            cell_width: 1,
+            cell_height: 1,
+        };
+        let mut source =
+            PtySource::spawn_command(command, window_size).unwrap();
+        let mut reader = source.take_reader().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = reader.read_to_end(&mut output);
+            let _ = sender.send(output);
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+
+        source.shutdown();
+        let output = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(String::from_utf8_lossy(&output).contains("started"));
+        let pid = source.process_id().unwrap();
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        source.wait();
+    }
+
+    #[test]
+    fn backend_drives_channel_source() {
+        use crate::{
+            BackendCommand, BackendSettings, ExportFormat, ExportRange,
+            PtyEvent, TerminalBackend,
+        };
+
+        let (source, peer) = ChannelSource::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut backend = TerminalBackend::with_source(
+            0,
+            egui::Context::default(),
+            sender,
+            Box::new(source),
+            BackendSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(peer.resize.recv().unwrap().num_cols, 80);
+
+        peer.output.send(b"hello\r\n\x1b[6n".to_vec()).unwrap();
+        // The cursor position report answers through the source.
+        assert_eq!(peer.input.recv().unwrap(), b"\x1b[2;1R");
+        let screen =
+            backend.export(ExportRange::Screen, &ExportFormat::PlainText);
+        assert!(screen.starts_with("hello\n\n"));
+
+        backend.process_command(BackendCommand::Write(b"ls\r".to_vec()));
+        assert_eq!(peer.input.recv().unwrap(), b"ls\r");
+
+        drop(peer.output);
+        while let Ok((_, event)) = receiver.recv() {
+            if matches!(event, PtyEvent::Exit) {
+                return;
+            }
+        }
+        panic!("no exit event");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn spawns_login_shells_and_names_missing_ones() {
+        use crate::{
+            BackendError, BackendSettings, HeadlessTerminal, SpawnError,
+        };
+        use std::time::Duration;
+
+        let settings = BackendSettings {
+            shell: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), "echo ready; read line".to_string()],
+            login_shell: true,
+            ..BackendSettings::default()
+        };
+        let terminal = HeadlessTerminal::new(settings.clone(), 20, 5).unwrap();
+        let timeout = Duration::from_secs(5);
+        terminal.backend().wait_for_regex("ready", timeout).unwrap();
+        let shell = terminal.backend().foreground_process().unwrap();
+        assert_eq!(shell.argv[0], "-sh");
+
+        let settings = BackendSettings {
+            shell: "/no/such/shell".to_string(),
+            ..settings
+        };
+        let Err(err) = HeadlessTerminal::new(settings, 20, 5) else {
+            panic!("spawned a missing shell");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), r#"shell "/no/such/shell" not found"#);
+        let BackendError::Spawn(SpawnError { shell, .. }) = err else {
+            panic!("not a spawn error: {err:?}");
+        };
+        assert_eq!(shell, "/no/such/shell");
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Called by the backend whenever the terminal changed or sent an event,
+/// so the host knows to redraw or to pump events.
+///
+/// An [`egui::Context`] converts into a waker that requests a repaint.
+/// Headless users can pass [`Waker::default`], which does nothing.
+#[derive(Clone, Default)]
+pub struct Waker(Option<Arc<dyn Fn() + Send + Sync>>);
+
+impl Waker {
+    pub fn new(wake: impl Fn() + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(wake)))
+    }
+
+    pub fn wake(&self) {
+        if let Some(wake) = &self.0 {
+            wake();
+        }
+    }
+}
+
+impl From<egui::Context> for Waker {
+    fn from(context: egui::Context) -> Self {
+        Self::new(move || context.request_repaint())
+    }
+}
+
+impl From<Option<Waker>> for Waker {
+    fn from(waker: Option<Waker>) -> Self {
+        waker.unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Waker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Waker").field(&self.0.is_some()).finish()
+    }
+}
@@ -9,6 +9,8 @@ pub enum BindingAction {
     Char(char),
     Esc(String),
     LinkOpen,
+    /// Folds or unfolds the output of the last finished shell command.
+    ToggleFold,
     Ignore,
 }
 
@@ -1,16 +1,43 @@
 mod backend;
 mod bindings;
 mod font;
+mod paste;
+mod snapshot;
 mod theme;
 mod types;
 mod view;
 
-pub use backend::settings::BackendSettings;
+pub use backend::export::{ExportFormat, ExportRange};
+pub use backend::headless::HeadlessTerminal;
+pub use backend::playback::{PlaybackCommand, PlaybackStatus, Recording};
+pub use backend::process::{ExitStatus, ProcessInfo, Signal, SignalTarget};
+pub use backend::recorder::RecordingSettings;
+pub use backend::settings::{
+    default_shell, BackendSettings, EnvInherit, EnvOverrideHook, EnvPolicy,
+    ExitBehavior, OverflowSettings, OverflowStorage, ShutdownPolicy,
+    StartupInput, StartupReady, TerminalConfig, DEFAULT_ENV_ALLOWLIST,
+    DEFAULT_SMART_SELECTION_PATTERNS,
+};
+pub use backend::shell_integration::{CommandInfo, CommandState};
+#[cfg(unix)]
+pub use backend::source::UnixSocketSource;
+pub use backend::source::{
+    BackendError, BackendSource, ChannelPeer, ChannelSource, DeviceSource,
+    PipeSource, PtySource, SpawnError,
+};
 pub use backend::{
-    BackendCommand, PtyEvent, SearchState, TerminalBackend, TerminalMode,
+    BackendCommand, Osc52, PtyEvent, RenderableCell, RenderableContent,
+    SearchState, TerminalBackend, TerminalMode, Waker,
+};
+pub use bindings::{
+    Binding, BindingAction, BindingsLayout, InputKind, KeyboardBinding,
 };
-pub use bindings::{Binding, BindingAction, InputKind, KeyboardBinding};
 pub use font::{FontSettings, TerminalFont};
+pub use paste::{
+    PasteConfirmHook, PasteDecision, PasteFilter, PastePolicy, PasteRequest,
+    PathQuoter,
+};
+pub use snapshot::TerminalSnapshot;
 pub use theme::{ColorPalette, TerminalTheme};
 pub use types::Size;
-pub use view::TerminalView;
+pub use view::{CopyPolicy, TerminalView};
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Transformation applied to clipboard text before it reaches the PTY.
+#[derive(Clone)]
+pub enum PasteFilter {
+    /// Strip a leading `$ ` shell prompt from every pasted line.
+    StripPromptPrefix,
+    /// Replace typographic quotes with their ASCII counterparts.
+    SmartQuotesToAscii,
+    /// Drop trailing line breaks so the last line is not executed.
+    TrimTrailingNewline,
+    /// Receives the text left by the filters before it and returns the
+    /// text passed on to the next one.
+    Custom(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl fmt::Debug for PasteFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StripPromptPrefix => write!(f, "StripPromptPrefix"),
+            Self::SmartQuotesToAscii => write!(f, "SmartQuotesToAscii"),
+            Self::TrimTrailingNewline => write!(f, "TrimTrailingNewline"),
+            Self::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl PasteFilter {
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Self::StripPromptPrefix => text
+                .split_inclusive('\n')
+                .map(|line| line.strip_prefix("$ ").unwrap_or(line))
+                .collect(),
+            Self::SmartQuotesToAscii => text
+                .chars()
+                .map(|c| match c {
+                    '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+                    '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+                    c => c,
+                })
+                .collect(),
+            Self::TrimTrailingNewline => {
+                text.trim_end_matches(['\n', '\r']).to_string()
+            },
+            Self::Custom(filter) => filter(text),
+        }
+    }
+}
+
+/// Clipboard text that is about to be pasted, after all filters ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasteRequest {
+    pub text: String,
+    /// The text contains at least one line break.
+    pub multiline: bool,
+    /// The text contains control characters other than tab and line breaks.
+    pub control_chars: bool,
+}
+
+impl PasteRequest {
+    pub fn new(text: String) -> Self {
+        let multiline = text.contains(['\n', '\r']);
+        let control_chars = text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'));
+
+        Self {
+            text,
+            multiline,
+            control_chars,
+        }
+    }
+}
+
+/// Answer of a [`PastePolicy`] confirmation hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteDecision {
+    Accept,
+    Reject,
+    /// Keep the paste on the backend until the host resolves it with
+    /// [`crate::TerminalBackend::accept_pending_paste`] or
+    /// [`crate::TerminalBackend::reject_pending_paste`].
+    Defer,
+}
+
+pub type PasteConfirmHook =
+    Arc<dyn Fn(&PasteRequest) -> PasteDecision + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct PastePolicy {
+    pub filters: Vec<PasteFilter>,
+    pub confirm_multiline: bool,
+    pub confirm_control_chars: bool,
+    /// Called when a paste needs confirmation. Without a hook such pastes
+    /// are deferred.
+    pub confirm: Option<PasteConfirmHook>,
+}
+
+impl fmt::Debug for PastePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PastePolicy")
+            .field("filters", &self.filters)
+            .field("confirm_multiline", &self.confirm_multiline)
+            .field("confirm_control_chars", &self.confirm_control_chars)
+            .field("confirm", &self.confirm.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl PastePolicy {
+    pub fn prepare(&self, text: &str) -> PasteRequest {
+        let text = self
+            .filters
+            .iter()
+            .fold(text.to_string(), |text, filter| filter.apply(&text));
+
+        PasteRequest::new(text)
+    }
+
+    pub fn needs_confirmation(&self, request: &PasteRequest) -> bool {
+        (self.confirm_multiline && request.multiline)
+            || (self.confirm_control_chars && request.control_chars)
+    }
+
+    pub fn decide(&self, request: &PasteRequest) -> PasteDecision {
+        if !self.needs_confirmation(request) {
+            return PasteDecision::Accept;
+        }
+
+        match &self.confirm {
+            Some(confirm) => confirm(request),
+            None => PasteDecision::Defer,
+        }
+    }
+}
+
+/// Turns the path of a file dropped onto the terminal into the text that
+/// is written to it, see [`crate::TerminalView::set_path_quoter`].
+pub type PathQuoter = Arc<dyn Fn(&Path) -> String + Send + Sync>;
+
+/// Quotes a path for POSIX shells. Paths made of safe characters are left
+/// untouched, paths with control characters use `$'...'` quoting so a line
+/// break in a file name can not execute the command line.
+pub fn posix_shell_quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let is_safe =
+        |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+
+    if !path.is_empty() && path.chars().all(is_safe) {
+        return path.into_owned();
+    }
+
+    if path.chars().any(char::is_control) {
+        let mut quoted = String::from("$'");
+        for c in path.chars() {
+            match c {
+                '\\' => quoted.push_str("\\\\"),
+                '\'' => quoted.push_str("\\'"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => {
+                    quoted.push_str(&format!("\\x{:02x}", c as u32))
+                },
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+/// Encodes pasted text the way the running application expects it.
+pub(crate) fn paste_payload(text: &str, bracketed: bool) -> Vec<u8> {
+    if bracketed {
+        // Bracketed paste mode: wrap text with markers and filter escape
+        // sequences that could terminate the paste early.
+        let mut payload = Vec::with_capacity(
+            text.len()
+                + BRACKETED_PASTE_START.len()
+                + BRACKETED_PASTE_END.len(),
+        );
+        payload.extend_from_slice(BRACKETED_PASTE_START);
+        payload.extend(text.bytes().filter(|b| *b != 0x1b && *b != 0x03));
+        payload.extend_from_slice(BRACKETED_PASTE_END);
+        payload
+    } else {
+        // Normal mode: replace newlines with carriage returns
+        text.replace("\r\n", "\r").replace('\n', "\r").into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        paste_payload, posix_shell_quote, PasteDecision, PasteFilter,
+        PastePolicy,
+    };
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn filters_are_applied_in_order() {
+        let policy = PastePolicy {
+            filters: vec![
+                PasteFilter::StripPromptPrefix,
+                PasteFilter::SmartQuotesToAscii,
+                PasteFilter::TrimTrailingNewline,
+            ],
+            ..Default::default()
+        };
+        let request = policy
+            .prepare("$ echo \u{201C}hi\u{201D}\n$ ls \u{2018}a\u{2019}\n\n");
+        assert_eq!(request.text, "echo \"hi\"\nls 'a'");
+        assert!(request.multiline);
+        assert!(!request.control_chars);
+    }
+
+    #[test]
+    fn single_line_paste_is_accepted_without_hook() {
+        let policy = PastePolicy {
+            filters: vec![PasteFilter::TrimTrailingNewline],
+            confirm_multiline: true,
+            ..Default::default()
+        };
+        let request = policy.prepare("ls -la\n");
+        assert_eq!(policy.decide(&request), PasteDecision::Accept);
+    }
+
+    #[test]
+    fn suspicious_paste_is_routed_through_hook() {
+        let mut policy = PastePolicy {
+            confirm_multiline: true,
+            confirm_control_chars: true,
+            ..Default::default()
+        };
+        let request = policy.prepare("rm -rf /tmp/x\x1b[A");
+        assert!(request.control_chars);
+        assert_eq!(policy.decide(&request), PasteDecision::Defer);
+
+        policy.confirm = Some(Arc::new(|_| PasteDecision::Reject));
+        assert_eq!(policy.decide(&request), PasteDecision::Reject);
+    }
+
+    #[test]
+    fn payload_encoding() {
+        assert_eq!(paste_payload("a\r\nb\nc", false), b"a\rb\rc");
+        assert_eq!(paste_payload("a\x1bb\x03", true), b"\x1b[200~ab\x1b[201~");
+    }
+
+    #[test]
+    fn posix_quoting() {
+        let quote = |p: &str| posix_shell_quote(Path::new(p));
+        assert_eq!(quote("/tmp/a-b_c.txt"), "/tmp/a-b_c.txt");
+        assert_eq!(quote("/tmp/my file"), "'/tmp/my file'");
+        assert_eq!(quote("/tmp/it's"), "'/tmp/it'\\''s'");
+        assert_eq!(quote("/tmp/a\nb"), "$'/tmp/a\\nb'");
+    }
+}
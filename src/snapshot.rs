@@ -0,0 +1,481 @@
+use std::fmt::Write as _;
+use std::io::{self, Result};
+use std::path::Path;
+
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use alacritty_terminal::grid::Dimensions;
+use alacritty_terminal::term::cell::Flags;
+use alacritty_terminal::term::TermMode;
+use alacritty_terminal::vte::ansi::{Color, NamedColor};
+use egui::Color32;
+
+use crate::backend::RenderableContent;
+use crate::theme::TerminalTheme;
+use crate::types::Size;
+use crate::view::{SEARCH_FOCUSED_HIGHLIGHT_COLOR, SEARCH_HIGHLIGHT_COLOR};
+
+const DEFAULT_FONT_SIZE: f32 = 14.0;
+const DEFAULT_FONT_FAMILY: &str = "Hack, DejaVu Sans Mono, monospace";
+
+/// Renders a [`RenderableContent`] without a GPU, as SVG or as a PNG
+/// rasterized on the CPU. Useful for documentation screenshots and golden
+/// tests.
+pub struct TerminalSnapshot<'a> {
+    content: &'a RenderableContent,
+    theme: TerminalTheme,
+    font_size: f32,
+    cell_size: Size,
+    font_family: String,
+    font_data: Option<Vec<u8>>,
+}
+
+struct Glyph {
+    x: f32,
+    y: f32,
+    width: f32,
+    c: char,
+    color: Color32,
+    bold: bool,
+    italic: bool,
+}
+
+/// Drawing operations shared by the SVG and PNG outputs.
+struct Scene {
+    width: f32,
+    height: f32,
+    rects: Vec<(f32, f32, f32, f32, Color32)>,
+    glyphs: Vec<Glyph>,
+}
+
+impl<'a> TerminalSnapshot<'a> {
+    pub fn new(content: &'a RenderableContent) -> Self {
+        let size = content.terminal_size;
+        Self {
+            content,
+            theme: TerminalTheme::default(),
+            font_size: DEFAULT_FONT_SIZE,
+            cell_size: Size::new(
+                size.cell_width as f32,
+                size.cell_height as f32,
+            ),
+            font_family: DEFAULT_FONT_FAMILY.to_string(),
+            font_data: None,
+        }
+    }
+
+    pub fn set_theme(mut self, theme: TerminalTheme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Font size in pixels and the size of one grid cell. The cell size
+    /// defaults to the metrics the backend was last resized with.
+    pub fn set_font_metrics(mut self, font_size: f32, cell_size: Size) -> Self {
+        self.font_size = font_size;
+        self.cell_size = cell_size;
+        self
+    }
+
+    /// CSS font family used by the SVG output.
+    pub fn set_font_family(mut self, font_family: &str) -> Self {
+        self.font_family = font_family.to_string();
+        self
+    }
+
+    /// TrueType/OpenType font used by the PNG output. Defaults to the Hack
+    /// font bundled with egui.
+    pub fn set_font_data(mut self, font_data: Vec<u8>) -> Self {
+        self.font_data = Some(font_data);
+        self
+    }
+
+    pub fn to_svg(&self) -> String {
+        let scene = self.scene();
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" \
+             height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+            w = round(scene.width),
+            h = round(scene.height),
+        );
+        for (x, y, width, height, color) in &scene.rects {
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{}/>",
+                round(*x),
+                round(*y),
+                round(*width),
+                round(*height),
+                svg_fill(*color),
+            );
+        }
+
+        let _ = writeln!(
+            svg,
+            "<g font-family=\"{}\" font-size=\"{}\" text-anchor=\"middle\" \
+             dominant-baseline=\"central\" xml:space=\"preserve\">",
+            escape_xml(&self.font_family),
+            self.font_size,
+        );
+        for glyph in &scene.glyphs {
+            let mut text = String::new();
+            text.push(glyph.c);
+            let _ = writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\"{}{}{}>{}</text>",
+                round(glyph.x + glyph.width / 2.0),
+                round(glyph.y + self.cell_size.height / 2.0),
+                svg_fill(glyph.color),
+                if glyph.bold {
+                    " font-weight=\"bold\""
+                } else {
+                    ""
+                },
+                if glyph.italic {
+                    " font-style=\"italic\""
+                } else {
+                    ""
+                },
+                escape_xml(&text),
+            );
+        }
+        svg.push_str("</g>\n</svg>\n");
+        svg
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let font = match &self.font_data {
+            Some(data) => FontArc::try_from_vec(data.clone()),
+            None => FontArc::try_from_slice(epaint_default_fonts::HACK_REGULAR),
+        }
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let scene = self.scene();
+        let mut canvas = Canvas::new(scene.width as u32, scene.height as u32);
+        for (x, y, width, height, color) in &scene.rects {
+            canvas.fill_rect(*x, *y, *width, *height, *color);
+        }
+
+        let font = font.as_scaled(PxScale::from(self.font_size));
+        let glyph_height = font.ascent() - font.descent();
+        for glyph in &scene.glyphs {
+            let id = font.glyph_id(glyph.c);
+            let advance = font.h_advance(id);
+            let position = ab_glyph::point(
+                glyph.x + (glyph.width - advance) / 2.0,
+                glyph.y
+                    + (self.cell_size.height - glyph_height) / 2.0
+                    + font.ascent(),
+            );
+            let Some(outline) = font.outline_glyph(
+                id.with_scale_and_position(self.font_size, position),
+            ) else {
+                continue;
+            };
+
+            let bounds = outline.px_bounds();
+            // There is no bold face, embolden by drawing twice.
+            let passes = if glyph.bold { 2 } else { 1 };
+            for pass in 0..passes {
+                outline.draw(|x, y, coverage| {
+                    canvas.blend(
+                        bounds.min.x as i32 + x as i32 + pass,
+                        bounds.min.y as i32 + y as i32,
+                        glyph.color,
+                        coverage,
+                    );
+                });
+            }
+        }
+
+        canvas.encode_png()
+    }
+
+    pub fn save_svg(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_svg())
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_png()?)
+    }
+
+    fn scene(&self) -> Scene {
+        let content = self.content;
+        let cell_width = self.cell_size.width;
+        let cell_height = self.cell_size.height;
+        let global_bg =
+            self.theme.get_color(Color::Named(NamedColor::Background));
+        let mut scene = Scene {
+            width: cell_width * content.terminal_size.columns() as f32,
+            height: cell_height * content.terminal_size.screen_lines() as f32,
+            rects: Vec::new(),
+            glyphs: Vec::new(),
+        };
+        scene
+            .rects
+            .push((0.0, 0.0, scene.width, scene.height, global_bg));
+
+        let show_cursor = content.terminal_mode.contains(TermMode::SHOW_CURSOR);
+        for indexed in &content.cells {
+            let flags = indexed.cell.flags;
+            if flags.contains(Flags::WIDE_CHAR_SPACER) {
+                continue;
+            }
+
+            let x = cell_width * indexed.point.column.0 as f32;
+            let line_num = indexed.point.line.0 + content.display_offset as i32;
+            let y = cell_height * line_num as f32;
+            let width = if flags.contains(Flags::WIDE_CHAR) {
+                cell_width * 2.0
+            } else {
+                cell_width
+            };
+
+            let is_selected = content
+                .selectable_range
+                .is_some_and(|r| r.contains(indexed.point));
+            let search = &content.search_state;
+            let is_search_match =
+                search.active && search.point_in_match(indexed.point).is_some();
+
+            let mut fg = self.theme.get_color(indexed.cell.fg);
+            let mut bg = self.theme.get_color(indexed.cell.bg);
+            if flags.contains(Flags::DIM) {
+                fg = fg.linear_multiply(0.7);
+            }
+            if flags.contains(Flags::INVERSE) || is_selected {
+                std::mem::swap(&mut fg, &mut bg);
+            }
+
+            if bg != global_bg {
+                scene.rects.push((x, y, width, cell_height, bg));
+            }
+            if is_search_match {
+                let color = if search.is_focused_match(indexed.point) {
+                    SEARCH_FOCUSED_HIGHLIGHT_COLOR
+                } else {
+                    SEARCH_HIGHLIGHT_COLOR
+                };
+                scene.rects.push((x, y, width, cell_height, color));
+            }
+            if show_cursor && content.cursor_point == indexed.point {
+                let cursor_color = self.theme.get_color(content.cursor.fg);
+                scene.rects.push((x, y, width, cell_height, cursor_color));
+                fg = bg;
+            }
+
+            let line_width = (cell_height * 0.08).max(1.0);
+            if flags.intersects(Flags::ALL_UNDERLINES) {
+                let underline_y = y + cell_height - line_width * 2.0;
+                scene.rects.push((x, underline_y, width, line_width, fg));
+            }
+            if flags.contains(Flags::STRIKEOUT) {
+                let strike_y = y + (cell_height - line_width) / 2.0;
+                scene.rects.push((x, strike_y, width, line_width, fg));
+            }
+
+            let c = indexed.cell.c;
+            if c != ' ' && c != '\t' && !flags.contains(Flags::HIDDEN) {
+                scene.glyphs.push(Glyph {
+                    x,
+                    y,
+                    width,
+                    c,
+                    color: fg,
+                    bold: flags.contains(Flags::BOLD),
+                    italic: flags.contains(Flags::ITALIC),
+                });
+            }
+        }
+
+        scene
+    }
+}
+
+/// Keeps SVG coordinates short and stable across platforms.
+fn round(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+fn svg_fill(color: Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let mut fill = format!(" fill=\"#{r:02x}{g:02x}{b:02x}\"");
+    if a < 255 {
+        let _ = write!(fill, " fill-opacity=\"{:.3}\"", a as f32 / 255.0);
+    }
+    fill
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Premultiplied RGBA pixel buffer.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; (width * height) as usize],
+        }
+    }
+
+    fn fill_rect(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color32,
+    ) {
+        let x0 = x.round().max(0.0) as i32;
+        let y0 = y.round().max(0.0) as i32;
+        let x1 = (x + width).round() as i32;
+        let y1 = (y + height).round() as i32;
+        for py in y0..y1 {
+            for px in x0..x1 {
+                self.blend(px, py, color, 1.0);
+            }
+        }
+    }
+
+    fn blend(&mut self, x: i32, y: i32, color: Color32, coverage: f32) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let coverage = coverage.clamp(0.0, 1.0);
+        let src = color.to_array().map(|c| c as f32 / 255.0 * coverage);
+        let dst = &mut self.pixels[(y as u32 * self.width + x as u32) as usize];
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = s + *d * (1.0 - src[3]);
+        }
+    }
+
+    fn encode_png(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for [r, g, b, a] in &self.pixels {
+            let color = Color32::from_rgba_premultiplied(
+                (r * 255.0).round() as u8,
+                (g * 255.0).round() as u8,
+                (b * 255.0).round() as u8,
+                (a * 255.0).round() as u8,
+            );
+            data.extend_from_slice(&color.to_srgba_unmultiplied());
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TerminalSnapshot;
+    use crate::backend::{RenderableCell, RenderableContent};
+    use crate::types::Size;
+    use alacritty_terminal::index::{Column, Line, Point};
+    use alacritty_terminal::selection::SelectionRange;
+    use alacritty_terminal::term::cell::{Cell, Flags};
+    use alacritty_terminal::term::TermMode;
+    use alacritty_terminal::vte::ansi::{Color, NamedColor};
+
+    fn content() -> RenderableContent {
+        let mut content = RenderableContent {
+            terminal_mode: TermMode::SHOW_CURSOR,
+            cursor_point: Point::new(Line(1), Column(0)),
+            ..RenderableContent::default()
+        };
+        for (column, c) in "a<b".chars().enumerate() {
+            let mut cell = Cell {
+                c,
+                ..Cell::default()
+            };
+            if column == 0 {
+                cell.fg = Color::Named(NamedColor::Red);
+                cell.flags = Flags::BOLD | Flags::UNDERLINE;
+            }
+            content.cells.push(RenderableCell {
+                point: Point::new(Line(0), Column(column)),
+                cell,
+            });
+        }
+        content.cursor = Cell {
+            fg: Color::Named(NamedColor::Green),
+            ..Cell::default()
+        };
+        content.cells.push(RenderableCell {
+            point: content.cursor_point,
+            cell: Cell::default(),
+        });
+        content
+    }
+
+    #[test]
+    fn svg_contains_styled_text_and_cursor() {
+        let content = content();
+        let svg = TerminalSnapshot::new(&content)
+            .set_font_metrics(14.0, Size::new(8.0, 16.0))
+            .to_svg();
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"640\" height=\"800\""));
+        assert!(svg.contains(
+            "<text x=\"4\" y=\"8\" fill=\"#ac4242\" font-weight=\"bold\">a"
+        ));
+        assert!(svg.contains(">&lt;</text>"));
+        // Underline and cursor.
+        assert!(svg.contains("width=\"8\" height=\"1.28\" fill=\"#ac4242\""));
+        assert!(svg.contains(
+            "<rect x=\"0\" y=\"16\" width=\"8\" height=\"16\" fill=\"#90a959\""
+        ));
+    }
+
+    #[test]
+    fn png_rasterizes_background_selection_and_cursor() {
+        let mut content = content();
+        let selected = Point::new(Line(0), Column(2));
+        content.selectable_range =
+            Some(SelectionRange::new(selected, selected, false));
+
+        let png = TerminalSnapshot::new(&content)
+            .set_font_metrics(14.0, Size::new(8.0, 16.0))
+            .to_png()
+            .unwrap();
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(png));
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (640, 800));
+
+        let pixel = |x: usize, y: usize| {
+            let i = (y * info.width as usize + x) * 4;
+            [data[i], data[i + 1], data[i + 2]]
+        };
+        // Background, selected cell (colors swapped) and cursor block.
+        assert_eq!(pixel(100, 100), [0x18, 0x18, 0x18]);
+        assert_eq!(pixel(16, 1), [0xd8, 0xd8, 0xd8]);
+        assert_eq!(pixel(1, 17), [0x90, 0xa9, 0x59]);
+        // The glyph inside the first cell is drawn in red.
+        assert!((0..8)
+            .flat_map(|x| (0..14).map(move |y| (x, y)))
+            .any(|(x, y)| pixel(x, y) == [0xac, 0x42, 0x42]));
+    }
+}
@@ -1,3 +1,4 @@
+use alacritty_terminal::grid::Dimensions;
 use alacritty_terminal::index::Point as TerminalGridPoint;
 use alacritty_terminal::term::cell;
 use alacritty_terminal::term::TermMode;
@@ -11,23 +12,38 @@ use egui::{Align2, Painter, Pos2, Rect, Response, Stroke, Vec2};
 use egui::{Color32, CornerRadius, Key};
 use egui::{Id, PointerButton};
 
+use crate::backend::shell_integration::CommandState;
 use crate::backend::BackendCommand;
+use crate::backend::RenderableContent;
 use crate::backend::TerminalBackend;
 use crate::backend::{LinkAction, MouseButton, SelectionType};
 use crate::bindings::Binding;
 use crate::bindings::{BindingAction, BindingsLayout, InputKind};
 use crate::font::TerminalFont;
+use crate::paste::{
+    posix_shell_quote, PasteDecision, PastePolicy, PasteRequest, PathQuoter,
+};
 use crate::theme::TerminalTheme;
 use crate::types::Size;
+use std::sync::Arc;
+use std::time::Duration;
 
 const EGUI_TERM_WIDGET_ID_PREFIX: &str = "egui_term::instance::";
-const SEARCH_HIGHLIGHT_COLOR: Color32 = Color32::from_rgb(255, 165, 0);
-const SEARCH_FOCUSED_HIGHLIGHT_COLOR: Color32 = Color32::from_rgb(255, 140, 0);
+pub(crate) const SEARCH_HIGHLIGHT_COLOR: Color32 =
+    Color32::from_rgb(255, 165, 0);
+pub(crate) const SEARCH_FOCUSED_HIGHLIGHT_COLOR: Color32 =
+    Color32::from_rgb(255, 140, 0);
+const AUTOSCROLL_LINES_PER_SECOND: f32 = 8.0;
+const FILE_HOVER_HIGHLIGHT_COLOR: Color32 = Color32::from_rgb(106, 159, 181);
+/// Width of the command gutter in cells: the marker and a duration of up
+/// to six characters.
+const GUTTER_COLUMNS: f32 = 8.0;
 
 #[derive(Debug, Clone)]
 enum InputAction {
     BackendCall(BackendCommand),
     WriteToClipboard(String),
+    DeferPaste(PasteRequest),
     Ignore,
 }
 
@@ -36,6 +52,9 @@ pub struct TerminalViewState {
     is_dragged: bool,
     scroll_pixels: f32,
     current_mouse_position_on_grid: TerminalGridPoint,
+    is_file_hovered: bool,
+    autoscroll_lines: f32,
+    gutter_width: f32,
 }
 
 pub struct TerminalView<'a> {
@@ -46,6 +65,39 @@ pub struct TerminalView<'a> {
     font: TerminalFont,
     theme: TerminalTheme,
     bindings_layout: BindingsLayout,
+    paste_policy: PastePolicy,
+    path_quoter: PathQuoter,
+    copy_policy: CopyPolicy,
+    command_gutter: bool,
+}
+
+/// Decides what the `Copy` / `Cut` shortcuts do: copy the selection to the
+/// clipboard or send `^C` / `^X` to the running program. Copying without a
+/// selection does nothing, so the clipboard keeps its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyPolicy {
+    /// Copy when Ctrl+Shift is held, send the control character otherwise.
+    CopyWithShift,
+    /// Copy and clear the selection when there is one, send the control
+    /// character otherwise.
+    CopyIfSelection,
+    /// Always copy the selection and never send the control character.
+    /// Without a selection the keys do nothing.
+    AlwaysCopy,
+    /// Always send the control character, whether or not there is a
+    /// selection. Copying is left to [`crate::BindingAction::Copy`].
+    AlwaysSend,
+}
+
+impl Default for CopyPolicy {
+    fn default() -> Self {
+        // Cmd+C never collides with ^C on Apple platforms.
+        if cfg!(any(target_os = "ios", target_os = "macos")) {
+            Self::AlwaysCopy
+        } else {
+            Self::CopyWithShift
+        }
+    }
 }
 
 impl Widget for TerminalView<'_> {
@@ -61,8 +113,10 @@ impl Widget for TerminalView<'_> {
         });
 
         self.focus(&layout)
-            .resize(&layout)
+            .resize(&layout, &mut state)
             .process_input(&layout, &mut state)
+            .autoscroll_selection(&layout, &mut state)
+            .process_dropped_files(&layout, &mut state)
             .show(&mut state, &layout, &painter);
 
         ui.memory_mut(|m| m.data.insert_temp(widget_id, state));
@@ -86,6 +140,10 @@ impl<'a> TerminalView<'a> {
             font: TerminalFont::default(),
             theme: TerminalTheme::default(),
             bindings_layout: BindingsLayout::new(),
+            paste_policy: PastePolicy::default(),
+            path_quoter: Arc::new(posix_shell_quote),
+            copy_policy: CopyPolicy::default(),
+            command_gutter: false,
         }
     }
 
@@ -122,6 +180,36 @@ impl<'a> TerminalView<'a> {
         self
     }
 
+    #[inline]
+    pub fn set_paste_policy(mut self, paste_policy: PastePolicy) -> Self {
+        self.paste_policy = paste_policy;
+        self
+    }
+
+    /// Sets the function used to quote paths of files dropped onto the
+    /// terminal. Defaults to [`posix_shell_quote`].
+    #[inline]
+    pub fn set_path_quoter(mut self, path_quoter: PathQuoter) -> Self {
+        self.path_quoter = path_quoter;
+        self
+    }
+
+    #[inline]
+    pub fn set_copy_policy(mut self, copy_policy: CopyPolicy) -> Self {
+        self.copy_policy = copy_policy;
+        self
+    }
+
+    /// Shows a gutter left of the grid with the exit status and runtime of
+    /// each shell command, see [`crate::backend::shell_integration`].
+    /// Clicking a finished command's marker folds its output, clicking the
+    /// bar below selects it.
+    #[inline]
+    pub fn set_command_gutter(mut self, command_gutter: bool) -> Self {
+        self.command_gutter = command_gutter;
+        self
+    }
+
     fn focus(self, layout: &Response) -> Self {
         if self.has_focus {
             layout.request_focus();
@@ -132,10 +220,17 @@ impl<'a> TerminalView<'a> {
         self
     }
 
-    fn resize(self, layout: &Response) -> Self {
+    fn resize(self, layout: &Response, state: &mut TerminalViewState) -> Self {
+        let cell_size = self.font.font_measure(&layout.ctx);
+        state.gutter_width = if self.command_gutter {
+            (cell_size.width * GUTTER_COLUMNS).min(layout.rect.width())
+        } else {
+            0.0
+        };
+        let grid_size = grid_rect(layout.rect, state.gutter_width).size();
         self.backend.process_command(BackendCommand::Resize(
-            Size::from(layout.rect.size()),
-            self.font.font_measure(&layout.ctx),
+            Size::from(grid_size),
+            cell_size,
         ));
 
         self
@@ -148,7 +243,9 @@ impl<'a> TerminalView<'a> {
     ) -> Self {
         let modifiers = layout.ctx.input(|i| i.modifiers);
         let has_focus = layout.has_focus();
-        let contains_pointer = layout.contains_pointer();
+        // Keep receiving pointer events while a selection drag leaves the
+        // widget, so it can be extended and finished outside of it.
+        let tracks_pointer = layout.contains_pointer() || state.is_dragged;
 
         let events: Vec<egui::Event> = layout.ctx.input(|i| {
             i.events
@@ -161,7 +258,7 @@ impl<'a> TerminalView<'a> {
                     | egui::Event::Paste(_) => has_focus,
                     egui::Event::MouseWheel { .. }
                     | egui::Event::PointerButton { .. }
-                    | egui::Event::PointerMoved(_) => contains_pointer,
+                    | egui::Event::PointerMoved(_) => tracks_pointer,
                     _ => false,
                 })
                 .cloned()
@@ -178,12 +275,14 @@ impl<'a> TerminalView<'a> {
                 | egui::Event::Cut
                 | egui::Event::Paste(_) => {
                     if layout.has_focus() {
-                        input_actions.push(process_keyboard_event(
+                        input_actions = process_keyboard_event(
                             event,
                             self.backend,
                             &self.bindings_layout,
+                            &self.paste_policy,
+                            self.copy_policy,
                             modifiers,
-                        ))
+                        )
                     }
                 },
                 egui::Event::MouseWheel { unit, delta, .. } => {
@@ -204,7 +303,17 @@ impl<'a> TerminalView<'a> {
                     pos,
                     ..
                 } => {
-                    if layout.contains_pointer() {
+                    if !state.is_dragged
+                        && is_in_gutter(layout.rect, state.gutter_width, pos)
+                    {
+                        if pressed && button == PointerButton::Primary {
+                            input_actions.push(process_gutter_click(
+                                layout.rect,
+                                self.backend.last_content(),
+                                pos,
+                            ))
+                        }
+                    } else if layout.contains_pointer() || state.is_dragged {
                         input_actions.push(process_button_click(
                             state,
                             layout,
@@ -218,7 +327,7 @@ impl<'a> TerminalView<'a> {
                     }
                 },
                 egui::Event::PointerMoved(pos) => {
-                    if layout.contains_pointer() {
+                    if layout.contains_pointer() || state.is_dragged {
                         input_actions = process_mouse_move(
                             state,
                             layout,
@@ -239,6 +348,9 @@ impl<'a> TerminalView<'a> {
                     InputAction::WriteToClipboard(data) => {
                         layout.ctx.copy_text(data);
                     },
+                    InputAction::DeferPaste(request) => {
+                        self.backend.set_pending_paste(request);
+                    },
                     InputAction::Ignore => {},
                 }
             }
@@ -247,6 +359,89 @@ impl<'a> TerminalView<'a> {
         self
     }
 
+    /// Scrolls the display while a selection is dragged above or below the
+    /// widget. The speed grows with the pointer distance from the edge.
+    fn autoscroll_selection(
+        self,
+        layout: &Response,
+        state: &mut TerminalViewState,
+    ) -> Self {
+        let (pointer_pos, dt) = layout
+            .ctx
+            .input(|i| (i.pointer.latest_pos(), i.stable_dt.min(0.1)));
+        let rect = grid_rect(layout.rect, state.gutter_width);
+        let (Some(pos), true) = (pointer_pos, state.is_dragged) else {
+            state.autoscroll_lines = 0.0;
+            return self;
+        };
+
+        let (distance, edge_y) = if pos.y < rect.min.y {
+            (rect.min.y - pos.y, 0.0)
+        } else if pos.y >= rect.max.y {
+            (rect.max.y - pos.y, rect.height() - 1.0)
+        } else {
+            state.autoscroll_lines = 0.0;
+            return self;
+        };
+
+        let cell_height =
+            self.backend.last_content().terminal_size.cell_height as f32;
+        let speed = (distance / cell_height + distance.signum())
+            * AUTOSCROLL_LINES_PER_SECOND;
+        state.autoscroll_lines += speed * dt;
+        let lines = state.autoscroll_lines.trunc();
+        state.autoscroll_lines -= lines;
+
+        if lines != 0.0 {
+            self.backend
+                .process_command(BackendCommand::Scroll(lines as i32));
+        }
+        self.backend.process_command(BackendCommand::SelectUpdate(
+            pos.x - rect.min.x,
+            edge_y,
+        ));
+        layout.ctx.request_repaint();
+
+        self
+    }
+
+    fn process_dropped_files(
+        self,
+        layout: &Response,
+        state: &mut TerminalViewState,
+    ) -> Self {
+        let (hovered, dropped, pointer_pos) = layout.ctx.input(|i| {
+            (
+                !i.raw.hovered_files.is_empty(),
+                i.raw.dropped_files.clone(),
+                i.pointer.hover_pos(),
+            )
+        });
+        // Some platforms do not report the pointer position during a drag,
+        // fall back to the focused terminal then.
+        let is_target = match pointer_pos {
+            Some(pos) => layout.rect.contains(pos),
+            None => layout.has_focus(),
+        };
+
+        state.is_file_hovered = hovered && is_target;
+        if dropped.is_empty() || !is_target {
+            return self;
+        }
+
+        let paths: Vec<String> = dropped
+            .iter()
+            .filter_map(|file| file.path.as_deref())
+            .map(|path| (self.path_quoter)(path))
+            .collect();
+        if !paths.is_empty() {
+            self.backend
+                .process_command(BackendCommand::Paste(paths.join(" ")));
+        }
+
+        self
+    }
+
     fn show(
         self,
         state: &mut TerminalViewState,
@@ -256,6 +451,7 @@ impl<'a> TerminalView<'a> {
         let content = self.backend.sync();
         let layout_min = layout.rect.min;
         let layout_max = layout.rect.max;
+        let grid_min = grid_rect(layout.rect, state.gutter_width).min;
         let cell_height = content.terminal_size.cell_height as f32;
         let cell_width = content.terminal_size.cell_width as f32;
         let global_bg =
@@ -306,10 +502,10 @@ impl<'a> TerminalView<'a> {
                     && content.search_state.is_focused_match(indexed.point);
 
                 let x =
-                    layout_min.x + (cell_width * indexed.point.column.0 as f32);
+                    grid_min.x + (cell_width * indexed.point.column.0 as f32);
                 let line_num =
                     indexed.point.line.0 + content.display_offset as i32;
-                let y = layout_min.y + (cell_height * line_num as f32);
+                let y = grid_min.y + (cell_height * line_num as f32);
 
                 let mut fg = self.theme.get_color(indexed.cell.fg);
                 let mut bg = self.theme.get_color(indexed.cell.bg);
@@ -398,74 +594,199 @@ impl<'a> TerminalView<'a> {
                     ));
                 }
             }
+
+            if state.gutter_width > 0.0 {
+                show_command_gutter(
+                    content,
+                    &self.theme,
+                    &font_id,
+                    layout,
+                    fonts,
+                    &mut shapes,
+                );
+            }
         });
 
+        if state.is_file_hovered {
+            shapes.push(Shape::Rect(RectShape::stroke(
+                layout.rect.shrink(1.0),
+                CornerRadius::ZERO,
+                Stroke::new(2.0, FILE_HOVER_HIGHLIGHT_COLOR),
+                egui::StrokeKind::Inside,
+            )));
+        }
+
         painter.extend(shapes);
     }
 }
 
+/// Draws a status bar along each command, with a marker and the
+/// runtime on its prompt line.
+fn show_command_gutter(
+    content: &RenderableContent,
+    theme: &TerminalTheme,
+    font_id: &egui::FontId,
+    layout: &Response,
+    fonts: &mut egui::epaint::FontsView,
+    shapes: &mut Vec<Shape>,
+) {
+    let cell_height = content.terminal_size.cell_height as f32;
+    let cell_width = content.terminal_size.cell_width as f32;
+    let screen_lines = content.terminal_size.screen_lines() as i32;
+    let origin = layout.rect.min;
+    let mut running = false;
+
+    for command in &content.commands {
+        let color = match command.state {
+            CommandState::Editing => continue,
+            CommandState::Running => {
+                running = true;
+                NamedColor::Yellow
+            },
+            CommandState::Finished(Some(0)) => NamedColor::Green,
+            CommandState::Finished(Some(_)) => NamedColor::Red,
+            CommandState::Finished(None) => NamedColor::BrightBlack,
+        };
+        let color = theme.get_color(Color::Named(color));
+
+        let first = command.prompt_line.max(0);
+        let last = command.end_line.unwrap_or(screen_lines).min(screen_lines);
+        if last > first {
+            shapes.push(Shape::Rect(RectShape::filled(
+                Rect::from_min_max(
+                    origin + Vec2::new(0.0, first as f32 * cell_height),
+                    origin
+                        + Vec2::new(
+                            (cell_width * 0.25).max(2.0),
+                            last as f32 * cell_height,
+                        ),
+                ),
+                CornerRadius::ZERO,
+                color,
+            )));
+        }
+
+        if command.prompt_line < 0 {
+            continue;
+        }
+        let center = origin
+            + Vec2::new(
+                cell_width,
+                (command.prompt_line as f32 + 0.5) * cell_height,
+            );
+        let radius = cell_height.min(cell_width * 2.0) * 0.3;
+        if command.folded {
+            shapes.push(Shape::circle_stroke(
+                center,
+                radius,
+                Stroke::new(1.5, color),
+            ));
+        } else {
+            shapes.push(Shape::circle_filled(center, radius, color));
+        }
+        if let Some(duration) = command.duration {
+            shapes.push(Shape::text(
+                fonts,
+                origin
+                    + Vec2::new(
+                        cell_width * (GUTTER_COLUMNS - 0.5),
+                        command.prompt_line as f32 * cell_height,
+                    ),
+                Align2::RIGHT_TOP,
+                format_duration(duration),
+                font_id.clone(),
+                theme.get_color(Color::Named(NamedColor::BrightBlack)),
+            ));
+        }
+    }
+
+    // Keep the runtime of a quiet command ticking.
+    if running {
+        layout.ctx.request_repaint_after(Duration::from_secs(1));
+    }
+}
+
+/// Area of the widget `rect` the terminal grid is drawn in, right of the
+/// gutter.
+fn grid_rect(mut rect: Rect, gutter_width: f32) -> Rect {
+    rect.min.x += gutter_width;
+    rect
+}
+
+fn is_in_gutter(rect: Rect, gutter_width: f32, position: Pos2) -> bool {
+    rect.contains(position) && position.x < rect.min.x + gutter_width
+}
+
+/// Shortest readable form of a command runtime, at most six characters.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else if seconds < 10 {
+        format!("{:.1}s", duration.as_secs_f32())
+    } else if seconds < 60 {
+        format!("{seconds}s")
+    } else if seconds < 3600 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else if seconds < 100 * 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60)
+    } else if seconds < 100 * 86400 {
+        format!("{}d{:02}h", seconds / 86400, seconds / 3600 % 24)
+    } else {
+        "99d+".to_string()
+    }
+}
+
+/// Folds or unfolds a finished command when its marker is clicked and
+/// selects its output when the bar below is.
+fn process_gutter_click(
+    rect: Rect,
+    content: &RenderableContent,
+    position: Pos2,
+) -> InputAction {
+    let line = ((position.y - rect.min.y)
+        / content.terminal_size.cell_height as f32) as i32;
+    content
+        .commands
+        .iter()
+        .find(|command| {
+            command.prompt_line <= line
+                && command.end_line.is_some_and(|end| line < end)
+        })
+        .filter(|command| matches!(command.state, CommandState::Finished(_)))
+        .map_or(InputAction::Ignore, |command| {
+            InputAction::BackendCall(if line == command.prompt_line {
+                BackendCommand::ToggleFold(command.id)
+            } else {
+                BackendCommand::SelectCommandOutput(command.id)
+            })
+        })
+}
+
 fn process_keyboard_event(
     event: egui::Event,
     backend: &TerminalBackend,
     bindings_layout: &BindingsLayout,
+    paste_policy: &PastePolicy,
+    copy_policy: CopyPolicy,
     modifiers: Modifiers,
-) -> InputAction {
-    match event {
+) -> Vec<InputAction> {
+    let action = match event {
         egui::Event::Text(text) => {
             process_text_event(&text, modifiers, backend, bindings_layout)
         },
-        egui::Event::Paste(text) => {
-            let terminal_mode = backend.last_content().terminal_mode;
-            InputAction::BackendCall(
-                if terminal_mode.contains(TermMode::BRACKETED_PASTE) {
-                    // Bracketed paste mode: wrap text with markers and filter escape sequences
-                    let mut payload = Vec::new();
-                    payload.extend_from_slice(b"\x1b[200~");
-                    // Filter out escape sequences that could terminate the paste early
-                    for byte in text.bytes() {
-                        if byte != 0x1b && byte != 0x03 {
-                            payload.push(byte);
-                        }
-                    }
-                    payload.extend_from_slice(b"\x1b[201~");
-                    BackendCommand::Write(payload)
-                } else {
-                    // Normal mode: replace newlines with carriage returns
-                    let processed =
-                        text.replace("\r\n", "\r").replace('\n', "\r");
-                    BackendCommand::Write(processed.into_bytes())
-                },
-            )
-        },
-        egui::Event::Copy => {
-            #[cfg(not(any(target_os = "ios", target_os = "macos")))]
-            if modifiers.contains(Modifiers::COMMAND | Modifiers::SHIFT) {
-                let content = backend.selectable_content();
-                InputAction::WriteToClipboard(content)
-            } else {
-                // Hotfix - Send ^C when there's not selection on view.
-                InputAction::BackendCall(BackendCommand::Write([0x3].to_vec()))
-            }
-            #[cfg(any(target_os = "ios", target_os = "macos"))]
-            {
-                let content = backend.selectable_content();
-                InputAction::WriteToClipboard(content)
-            }
-        },
-        egui::Event::Cut => {
-            #[cfg(not(any(target_os = "ios", target_os = "macos")))]
-            if modifiers.contains(Modifiers::COMMAND | Modifiers::SHIFT) {
-                let content = backend.selectable_content();
-                InputAction::WriteToClipboard(content)
-            } else {
-                // Hotfix - Send ^X when there's not selection on view.
-                InputAction::BackendCall(BackendCommand::Write([0x18].to_vec()))
-            }
-            #[cfg(any(target_os = "ios", target_os = "macos"))]
-            {
-                let content = backend.selectable_content();
-                InputAction::WriteToClipboard(content)
-            }
+        egui::Event::Paste(text) => process_paste_event(&text, paste_policy),
+        egui::Event::Copy | egui::Event::Cut => {
+            let selection = backend
+                .last_content()
+                .selectable_range
+                .map(|_| backend.selectable_content());
+            return process_clipboard_event(
+                &event,
+                copy_policy,
+                modifiers,
+                selection,
+            );
         },
         egui::Event::Key {
             key,
@@ -480,6 +801,62 @@ fn process_keyboard_event(
             pressed,
         ),
         _ => InputAction::Ignore,
+    };
+
+    vec![action]
+}
+
+/// Handles the `Copy` and `Cut` events egui emits for Ctrl+C / Ctrl+X (or
+/// Cmd+C / Cmd+X on macOS). `selection` is `None` when nothing is selected.
+fn process_clipboard_event(
+    event: &egui::Event,
+    copy_policy: CopyPolicy,
+    modifiers: Modifiers,
+    selection: Option<String>,
+) -> Vec<InputAction> {
+    let control_char = match event {
+        egui::Event::Copy => 0x03,
+        egui::Event::Cut => 0x18,
+        _ => return vec![InputAction::Ignore],
+    };
+    let send_control_char = || {
+        vec![InputAction::BackendCall(BackendCommand::Write(vec![
+            control_char,
+        ]))]
+    };
+    let copy = |selection: Option<String>| match selection {
+        Some(content) => vec![InputAction::WriteToClipboard(content)],
+        None => vec![InputAction::Ignore],
+    };
+
+    match copy_policy {
+        CopyPolicy::CopyWithShift => {
+            if modifiers.contains(Modifiers::COMMAND | Modifiers::SHIFT) {
+                copy(selection)
+            } else {
+                send_control_char()
+            }
+        },
+        CopyPolicy::CopyIfSelection => match selection {
+            Some(content) => vec![
+                InputAction::WriteToClipboard(content),
+                InputAction::BackendCall(BackendCommand::ClearSelection),
+            ],
+            None => send_control_char(),
+        },
+        CopyPolicy::AlwaysCopy => copy(selection),
+        CopyPolicy::AlwaysSend => send_control_char(),
+    }
+}
+
+fn process_paste_event(text: &str, paste_policy: &PastePolicy) -> InputAction {
+    let request = paste_policy.prepare(text);
+    match paste_policy.decide(&request) {
+        PasteDecision::Accept => {
+            InputAction::BackendCall(BackendCommand::Paste(request.text))
+        },
+        PasteDecision::Reject => InputAction::Ignore,
+        PasteDecision::Defer => InputAction::DeferPaste(request),
     }
 }
 
@@ -538,6 +915,9 @@ fn process_keyboard_key(
         BindingAction::Esc(seq) => InputAction::BackendCall(
             BackendCommand::Write(seq.as_bytes().to_vec()),
         ),
+        BindingAction::ToggleFold => {
+            InputAction::BackendCall(BackendCommand::ToggleLastFold)
+        },
         _ => InputAction::Ignore,
     }
 }
@@ -658,7 +1038,9 @@ fn process_left_button_pressed(
     position: Pos2,
 ) -> InputAction {
     state.is_dragged = true;
-    InputAction::BackendCall(build_start_select_command(layout, position))
+    InputAction::BackendCall(build_start_select_command(
+        layout, state, position,
+    ))
 }
 
 fn process_left_button_released(
@@ -671,7 +1053,9 @@ fn process_left_button_released(
 ) -> InputAction {
     state.is_dragged = false;
     if layout.double_clicked() || layout.triple_clicked() {
-        InputAction::BackendCall(build_start_select_command(layout, position))
+        InputAction::BackendCall(build_start_select_command(
+            layout, state, position,
+        ))
     } else {
         let terminal_content = backend.last_content();
         let binding_action = bindings_layout.get_action(
@@ -693,8 +1077,10 @@ fn process_left_button_released(
 
 fn build_start_select_command(
     layout: &Response,
+    state: &TerminalViewState,
     cursor_position: Pos2,
 ) -> BackendCommand {
+    let grid_min = grid_rect(layout.rect, state.gutter_width).min;
     let selection_type = if layout.double_clicked() {
         SelectionType::Semantic
     } else if layout.triple_clicked() {
@@ -705,8 +1091,8 @@ fn build_start_select_command(
 
     BackendCommand::SelectStart(
         selection_type,
-        cursor_position.x - layout.rect.min.x,
-        cursor_position.y - layout.rect.min.y,
+        cursor_position.x - grid_min.x,
+        cursor_position.y - grid_min.y,
     )
 }
 
@@ -718,8 +1104,9 @@ fn process_mouse_move(
     modifiers: &Modifiers,
 ) -> Vec<InputAction> {
     let terminal_content = backend.last_content();
-    let cursor_x = position.x - layout.rect.min.x;
-    let cursor_y = position.y - layout.rect.min.y;
+    let grid_min = grid_rect(layout.rect, state.gutter_width).min;
+    let cursor_x = position.x - grid_min.x;
+    let cursor_y = position.y - grid_min.y;
     state.current_mouse_position_on_grid = TerminalBackend::selection_point(
         cursor_x,
         cursor_y,
@@ -759,3 +1146,189 @@ fn process_mouse_move(
 
     actions
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_duration, grid_rect, is_in_gutter, process_clipboard_event,
+        process_gutter_click, CopyPolicy, InputAction,
+    };
+    use crate::backend::{BackendCommand, RenderableContent};
+    use crate::{CommandInfo, CommandState};
+    use egui::{Modifiers, Pos2, Rect};
+    use std::time::Duration;
+
+    fn copy_shift() -> Modifiers {
+        Modifiers::COMMAND | Modifiers::SHIFT
+    }
+
+    #[test]
+    fn copy_with_shift_policy() {
+        let actions = process_clipboard_event(
+            &egui::Event::Copy,
+            CopyPolicy::CopyWithShift,
+            copy_shift(),
+            Some("selected".into()),
+        );
+        assert!(matches!(
+            actions.as_slice(),
+            [InputAction::WriteToClipboard(text)] if text == "selected"
+        ));
+
+        let actions = process_clipboard_event(
+            &egui::Event::Copy,
+            CopyPolicy::CopyWithShift,
+            Modifiers::COMMAND,
+            Some("selected".into()),
+        );
+        assert!(matches!(
+            actions.as_slice(),
+            [InputAction::BackendCall(BackendCommand::Write(bytes))]
+                if bytes == &[0x03]
+        ));
+
+        let actions = process_clipboard_event(
+            &egui::Event::Copy,
+            CopyPolicy::CopyWithShift,
+            copy_shift(),
+            None,
+        );
+        assert!(matches!(actions.as_slice(), [InputAction::Ignore]));
+    }
+
+    #[test]
+    fn copy_if_selection_policy() {
+        let actions = process_clipboard_event(
+            &egui::Event::Copy,
+            CopyPolicy::CopyIfSelection,
+            Modifiers::COMMAND,
+            Some("selected".into()),
+        );
+        assert!(matches!(
+            actions.as_slice(),
+            [
+                InputAction::WriteToClipboard(text),
+                InputAction::BackendCall(BackendCommand::ClearSelection),
+            ] if text == "selected"
+        ));
+
+        let actions = process_clipboard_event(
+            &egui::Event::Cut,
+            CopyPolicy::CopyIfSelection,
+            Modifiers::COMMAND,
+            None,
+        );
+        assert!(matches!(
+            actions.as_slice(),
+            [InputAction::BackendCall(BackendCommand::Write(bytes))]
+                if bytes == &[0x18]
+        ));
+    }
+
+    #[test]
+    fn always_copy_and_always_send_policies() {
+        let actions = process_clipboard_event(
+            &egui::Event::Cut,
+            CopyPolicy::AlwaysCopy,
+            Modifiers::COMMAND,
+            None,
+        );
+        assert!(matches!(actions.as_slice(), [InputAction::Ignore]));
+
+        let actions = process_clipboard_event(
+            &egui::Event::Copy,
+            CopyPolicy::AlwaysSend,
+            copy_shift(),
+            Some("selected".into()),
+        );
+        assert!(matches!(
+            actions.as_slice(),
+            [InputAction::BackendCall(BackendCommand::Write(bytes))]
+                if bytes == &[0x03]
+        ));
+    }
+
+    #[test]
+    fn non_clipboard_events_are_ignored() {
+        let actions = process_clipboard_event(
+            &egui::Event::Text("c".into()),
+            CopyPolicy::AlwaysCopy,
+            Modifiers::NONE,
+            None,
+        );
+        assert!(matches!(actions.as_slice(), [InputAction::Ignore]));
+    }
+
+    #[test]
+    fn command_durations_fit_the_gutter() {
+        let formatted: Vec<_> =
+            [0.25, 4.56, 42.0, 305.0, 7380.0, 360_000.0, 374_400.0, 1e8]
+                .into_iter()
+                .map(|seconds| {
+                    format_duration(Duration::from_secs_f64(seconds))
+                })
+                .collect();
+        assert_eq!(
+            formatted,
+            [
+                "250ms", "4.6s", "42s", "5m05s", "2h03m", "4d04h", "4d08h",
+                "99d+"
+            ]
+        );
+        let longest = format_duration(Duration::from_secs(100 * 86400 - 1));
+        assert_eq!(longest, "99d23h");
+        assert!(longest.len() as f32 <= super::GUTTER_COLUMNS - 2.0);
+    }
+
+    #[test]
+    fn gutter_narrows_the_grid_and_takes_its_clicks() {
+        let rect =
+            Rect::from_min_size(Pos2::new(10.0, 20.0), [800.0, 600.0].into());
+        let grid = grid_rect(rect, 80.0);
+        assert_eq!(
+            (grid.min.x, grid.width(), grid.height()),
+            (90.0, 720.0, 600.0)
+        );
+        assert_eq!(grid_rect(rect, 0.0), rect);
+        assert!(is_in_gutter(rect, 80.0, Pos2::new(89.0, 30.0)));
+        assert!(!is_in_gutter(rect, 80.0, Pos2::new(90.0, 30.0)));
+        assert!(!is_in_gutter(rect, 80.0, Pos2::new(5.0, 30.0)));
+        assert!(!is_in_gutter(rect, 0.0, Pos2::new(10.0, 30.0)));
+
+        let mut content = RenderableContent::default();
+        content.terminal_size.cell_height = 20;
+        let command = CommandInfo {
+            id: 7,
+            prompt_line: 1,
+            output_line: Some(2),
+            end_line: Some(4),
+            state: CommandState::Finished(Some(0)),
+            duration: None,
+            folded: false,
+        };
+        content.commands = vec![
+            command,
+            CommandInfo {
+                id: 8,
+                prompt_line: 4,
+                end_line: None,
+                state: CommandState::Running,
+                ..command
+            },
+        ];
+        let click = |line: f32| {
+            let position = Pos2::new(20.0, 20.0 + line * 20.0 + 5.0);
+            process_gutter_click(rect, &content, position)
+        };
+        assert!(matches!(
+            click(1.0),
+            InputAction::BackendCall(BackendCommand::ToggleFold(7))
+        ));
+        assert!(matches!(
+            click(3.0),
+            InputAction::BackendCall(BackendCommand::SelectCommandOutput(7))
+        ));
+        assert!(matches!(click(0.0), InputAction::Ignore));
+        assert!(matches!(click(4.0), InputAction::Ignore));
+    }
+}